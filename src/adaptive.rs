@@ -0,0 +1,271 @@
+// Adaptive finger ranges for long unattended sessions, turned on in [adaptive] in glove.toml.
+//
+// Instead of only ever widening, each finger's range follows low and high percentiles of a
+// sliding window of recent readings. A reading far from the median of the last few is
+// dropped as a spike. The range widens straight away, but only shrinks slowly, so a
+// while spent holding one pose doesn't collapse it. Starts from the calibration if there is
+// one, and A freezes the overlay glove's ranges once they're good.
+
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use bevy::app::{App, Plugin, Update};
+use bevy::ecs::system::{Query, Res};
+use bevy::input::{keyboard::KeyCode, ButtonInput};
+use bevy::log::info;
+use serde::Deserialize;
+
+use crate::calibration::percentile;
+use crate::ruka::{ActiveHands, Handedness, RukaInput};
+
+/// How many of the latest readings a new one is compared against for spikes.
+const RECENT: usize = 5;
+/// How many readings are windowed before spikes are filtered.
+const MIN_WINDOW: usize = 100;
+/// How many readings between working out the percentiles again.
+const RECOMPUTE_EVERY: usize = 64;
+
+pub struct AdaptiveRangePlugin;
+
+impl Plugin for AdaptiveRangePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Update, toggle_freeze)
+        ;
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AdaptiveConfig {
+    pub enabled: bool,
+    /// Readings in the sliding window, per finger
+    pub window: usize,
+    /// Percentiles of the window that make the ends of the range, 0 to 1
+    pub low_percentile: f32,
+    pub high_percentile: f32,
+    /// How far from the recent median a reading can be, as a fraction of the range, before
+    /// it counts as a spike
+    pub spike_threshold: f32,
+    /// Time constant in seconds of the range shrinking towards the window's percentiles
+    pub decay_secs: f32,
+}
+
+impl Default for AdaptiveConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            window: 6000,
+            low_percentile: 0.02,
+            high_percentile: 0.98,
+            spike_threshold: 0.5,
+            decay_secs: 1800.0,
+        }
+    }
+}
+
+/// Follows the range of one finger's readings.
+#[derive(Debug, Clone)]
+pub struct RangeTracker {
+    config: AdaptiveConfig,
+    /// Readings that weren't spikes, oldest first
+    window: VecDeque<f32>,
+    /// The latest readings, spikes included, so a lasting jump stops counting as one
+    recent: VecDeque<f32>,
+    since_recompute: usize,
+    /// Percentiles of the window as of the last recompute
+    target: Option<(f32, f32)>,
+    range: Option<(f32, f32)>,
+    last_update: Option<Instant>,
+}
+
+impl RangeTracker {
+    pub fn new(config: &AdaptiveConfig) -> Self {
+        Self {
+            config: config.clone(),
+            window: VecDeque::with_capacity(config.window),
+            recent: VecDeque::with_capacity(RECENT),
+            since_recompute: 0,
+            target: None,
+            range: None,
+            last_update: None,
+        }
+    }
+
+    /// Forgets everything seen so far.
+    pub fn reset(&mut self) {
+        *self = Self::new(&self.config);
+    }
+
+    /// Starts from a known range, e.g. a calibration, and forgets the window.
+    pub fn seed(&mut self, low: f32, high: f32) {
+        self.reset();
+        self.range = Some((low.min(high), low.max(high)));
+    }
+
+    /// Takes in a reading and returns the (low, high) range so far.
+    pub fn update(&mut self, value: f32, now: Instant) -> Option<(f32, f32)> {
+        if self.recent.len() == RECENT {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(value);
+        if self.is_spike(value) {
+            return self.range;
+        }
+
+        if self.window.len() >= self.config.window.max(1) {
+            self.window.pop_front();
+        }
+        self.window.push_back(value);
+        self.since_recompute += 1;
+        if self.target.is_none() || self.since_recompute >= RECOMPUTE_EVERY {
+            self.since_recompute = 0;
+            let mut sorted: Vec<f32> = self.window.iter().copied().collect();
+            sorted.sort_by(f32::total_cmp);
+            self.target = Some((percentile(&sorted, self.config.low_percentile), percentile(&sorted, self.config.high_percentile)));
+        }
+
+        let elapsed = self.last_update.map(|last| now.duration_since(last).as_secs_f32()).unwrap_or_default();
+        self.last_update = Some(now);
+        let (target_low, target_high) = self.target?;
+        let decay = 1.0 - (-elapsed / self.config.decay_secs.max(f32::EPSILON)).exp();
+        self.range = Some(match self.range {
+            None => (target_low, target_high),
+            Some((low, high)) => (
+                match target_low < low {
+                    true => target_low,
+                    false => low + (target_low - low) * decay,
+                },
+                match target_high > high {
+                    true => target_high,
+                    false => high + (target_high - high) * decay,
+                },
+            ),
+        });
+        self.range
+    }
+
+    fn is_spike(&self, value: f32) -> bool {
+        let Some((low, high)) = self.range else {
+            return false;
+        };
+        if self.window.len() < MIN_WINDOW || high <= low {
+            return false;
+        }
+        let mut recent: Vec<f32> = self.recent.iter().copied().collect();
+        recent.sort_by(f32::total_cmp);
+        let median = recent[recent.len() / 2];
+        (value - median).abs() > self.config.spike_threshold * (high - low)
+    }
+}
+
+fn toggle_freeze(
+    keys: Res<ButtonInput<KeyCode>>,
+    hands: Res<ActiveHands>,
+    mut gloves: Query<(&Handedness, &mut RukaInput)>,
+) {
+    if !keys.just_pressed(KeyCode::KeyA) {
+        return;
+    }
+    let Some((hand, mut ruka)) = gloves.iter_mut().find(|(hand, _)| **hand == hands.debug) else {
+        return;
+    };
+    if !ruka.is_adaptive() {
+        return;
+    }
+    let frozen = !ruka.is_range_frozen();
+    ruka.set_range_frozen(frozen);
+    info!("{:?} glove ranges {}", hand, if frozen { "frozen" } else { "adapting" });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn tracker(window: usize) -> RangeTracker {
+        RangeTracker::new(&AdaptiveConfig {
+            enabled: true,
+            window,
+            low_percentile: 0.0,
+            high_percentile: 1.0,
+            spike_threshold: 0.5,
+            decay_secs: 10.0,
+        })
+    }
+
+    /// Feeds `values` in order, all at `now`, and returns the range after the last one.
+    fn feed(tracker: &mut RangeTracker, values: impl IntoIterator<Item = f32>, now: Instant) -> Option<(f32, f32)> {
+        values.into_iter().map(|value| tracker.update(value, now)).last().flatten()
+    }
+
+    #[test]
+    fn starts_from_the_first_reading() {
+        let now = Instant::now();
+        assert_eq!(tracker(100).update(1000.0, now), Some((1000.0, 1000.0)));
+
+        // A window of nothing still holds the latest reading
+        let mut empty = tracker(0);
+        assert_eq!(empty.update(1000.0, now), Some((1000.0, 1000.0)));
+        assert_eq!(empty.window.len(), 1);
+        feed(&mut empty, [2000.0; RECOMPUTE_EVERY], now);
+        assert_eq!(empty.window, [2000.0]);
+    }
+
+    #[test]
+    fn seeds_either_way_round() {
+        let mut tracker = tracker(100);
+        tracker.seed(5000.0, 1000.0);
+        assert_eq!(tracker.range, Some((1000.0, 5000.0)));
+        assert!(tracker.window.is_empty());
+        // A reading inside the seeded range doesn't narrow it straight away
+        assert_eq!(tracker.update(3000.0, Instant::now()), Some((1000.0, 5000.0)));
+    }
+
+    #[test]
+    fn widens_at_once_and_shrinks_slowly() {
+        let start = Instant::now();
+        let mut tracker = tracker(RECOMPUTE_EVERY);
+        assert_eq!(tracker.update(1000.0, start), Some((1000.0, 1000.0)));
+        // Widens as soon as the percentiles are worked out again
+        let wide = (0..RECOMPUTE_EVERY).map(|i| if i % 2 == 0 { 1000.0 } else { 5000.0 });
+        assert_eq!(feed(&mut tracker, wide, start), Some((1000.0, 5000.0)));
+
+        // Holding one pose fills the window, but no time has passed
+        assert_eq!(feed(&mut tracker, [3000.0; RECOMPUTE_EVERY], start), Some((1000.0, 5000.0)));
+
+        // One time constant later it has come most of the way in
+        let (low, high) = tracker.update(3000.0, start + Duration::from_secs(10)).unwrap();
+        let shrunk = 2000.0 * (-1.0f32).exp();
+        assert!((low - (3000.0 - shrunk)).abs() < 1.0, "{}", low);
+        assert!((high - (3000.0 + shrunk)).abs() < 1.0, "{}", high);
+    }
+
+    #[test]
+    fn drops_spikes_until_they_last() {
+        let now = Instant::now();
+        let mut tracker = tracker(1000);
+        let readings = (0..MIN_WINDOW).map(|i| if i % 2 == 0 { 1000.0 } else { 2000.0 });
+        assert_eq!(feed(&mut tracker, readings, now), Some((1000.0, 2000.0)));
+
+        // Too far from the recent median to be real
+        assert_eq!(tracker.update(9000.0, now), Some((1000.0, 2000.0)));
+        assert_eq!(tracker.window.len(), MIN_WINDOW);
+        tracker.update(9000.0, now);
+        assert_eq!(tracker.window.len(), MIN_WINDOW);
+
+        // Once most of the recent readings agree, it's where the finger is now
+        tracker.update(9000.0, now);
+        assert_eq!(tracker.window.back(), Some(&9000.0));
+    }
+
+    #[test]
+    fn no_spikes_before_the_window_fills() {
+        let now = Instant::now();
+        let mut tracker = tracker(1000);
+        feed(&mut tracker, [1000.0, 2000.0].repeat(RECOMPUTE_EVERY / 2), now);
+        tracker.update(9000.0, now);
+        assert_eq!(tracker.window.back(), Some(&9000.0));
+    }
+}
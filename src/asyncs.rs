@@ -4,11 +4,22 @@
 use std::future::Future;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use bevy::app::{App, Plugin, Update};
+use bevy::app::{App, AppExit, Last, Plugin, Update};
+use bevy::ecs::event::Events;
 use bevy::ecs::system::Resource;
 use bevy::ecs::world::World;
+use bevy::log::{info, warn};
 use tokio::{runtime::Runtime, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+/// How long background tasks get to wind down after the app exits, e.g. to disconnect gloves.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+/// How long the runtime blocks at a time while waiting for them, before running any main
+/// thread work they asked for in the meantime.
+const SHUTDOWN_POLL: Duration = Duration::from_millis(10);
 
 /// An internal struct keeping track of how many ticks have elapsed since the start of the program.
 #[derive(Resource)]
@@ -68,6 +79,7 @@ impl Plugin for TokioTasksPlugin {
         });
         app.insert_resource(TokioTasksRuntime::new(ticks, runtime, update_watch_rx));
         app.add_systems(Update, tick_runtime_update);
+        app.add_systems(Last, shutdown_on_exit);
     }
 }
 
@@ -92,6 +104,18 @@ pub fn tick_runtime_update(world: &mut World) {
     }
 }
 
+/// Once the app is exiting, tells every background task to stop through their
+/// [`shutdown_token`](TaskContext::shutdown_token) and waits up to [`SHUTDOWN_TIMEOUT`] for them
+/// before shutting the runtime down. Runs last so it sees the [`AppExit`] of the same frame.
+fn shutdown_on_exit(world: &mut World) {
+    if world.resource::<Events<AppExit>>().is_empty() {
+        return;
+    }
+    if let Some(runtime) = world.remove_resource::<TokioTasksRuntime>() {
+        runtime.shutdown(world);
+    }
+}
+
 type MainThreadCallback = Box<dyn FnOnce(MainThreadContext) + Send + 'static>;
 
 /// The Bevy [`Resource`] which stores the Tokio [`Runtime`] and allows for spawning new
@@ -107,6 +131,10 @@ struct TokioTasksRuntimeInner {
     update_watch_rx: tokio::sync::watch::Receiver<()>,
     update_run_tx: tokio::sync::mpsc::UnboundedSender<MainThreadCallback>,
     update_run_rx: tokio::sync::mpsc::UnboundedReceiver<MainThreadCallback>,
+    /// Cancelled when the app exits
+    shutdown: CancellationToken,
+    /// Every spawned background task, so shutdown can wait for them
+    tracker: TaskTracker,
 }
 
 impl TokioTasksRuntime {
@@ -123,6 +151,8 @@ impl TokioTasksRuntime {
             update_watch_rx,
             update_run_tx,
             update_run_rx,
+            shutdown: CancellationToken::new(),
+            tracker: TaskTracker::new(),
         }))
     }
 
@@ -150,9 +180,10 @@ impl TokioTasksRuntime {
             update_watch_rx: inner.update_watch_rx.clone(),
             ticks: inner.ticks.clone(),
             update_run_tx: inner.update_run_tx.clone(),
+            shutdown: inner.shutdown.clone(),
         };
         let future = spawnable_task(context);
-        inner.runtime.spawn(future)
+        inner.runtime.spawn(inner.tracker.track_future(future))
     }
 
     /// Execute all of the requested runnables on the main thread.
@@ -171,6 +202,31 @@ impl TokioTasksRuntime {
             runnable(context);
         }
     }
+
+    /// Cancels the shutdown token and keeps the runtime and main thread work going until every
+    /// background task has returned or [`SHUTDOWN_TIMEOUT`] is up. Whatever is left after that
+    /// is dropped along with the runtime.
+    fn shutdown(mut self, world: &mut World) {
+        info!("Stopping background tasks...");
+        self.0.shutdown.cancel();
+        self.0.tracker.close();
+
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+        while !self.0.tracker.is_empty() && Instant::now() < deadline {
+            let inner = &self.0;
+            inner.runtime.block_on(async {
+                let _ = tokio::time::timeout(SHUTDOWN_POLL, inner.tracker.wait()).await;
+            });
+            let current_tick = self.0.ticks.load(Ordering::SeqCst);
+            self.execute_main_thread_work(world, current_tick);
+        }
+
+        if !self.0.tracker.is_empty() {
+            warn!("{} background tasks did not stop within {:?}", self.0.tracker.len(), SHUTDOWN_TIMEOUT);
+        }
+        let inner = *self.0;
+        inner.runtime.shutdown_timeout(SHUTDOWN_POLL);
+    }
 }
 
 /// The context arguments which are available to main thread callbacks requested using
@@ -189,6 +245,7 @@ pub struct TaskContext {
     update_watch_rx: tokio::sync::watch::Receiver<()>,
     update_run_tx: tokio::sync::mpsc::UnboundedSender<MainThreadCallback>,
     ticks: Arc<AtomicUsize>,
+    shutdown: CancellationToken,
 }
 
 impl TaskContext {
@@ -199,6 +256,12 @@ impl TaskContext {
         self.ticks.load(Ordering::SeqCst)
     }
 
+    /// Cancelled when the app exits. Tasks that hold on to something outside the process,
+    /// like a Bluetooth connection, should let go of it and return once this fires.
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.shutdown.clone()
+    }
+
     /// Sleeps the background task until a given number of main thread updates have occurred. If
     /// you instead want to sleep for a given length of wall-clock time, call the normal Tokio sleep
     /// function.
@@ -225,9 +288,9 @@ impl TaskContext {
     {
         let (output_tx, output_rx) = tokio::sync::oneshot::channel();
         if self.update_run_tx.send(Box::new(move |ctx| {
-            if output_tx.send(runnable(ctx)).is_err() {
-                panic!("Failed to sent output from operation run on main thread back to waiting task");
-            }
+            // The waiting task may have been dropped in the meantime, e.g. a cancelled session.
+            // The operation has still run, there is just nobody left to tell.
+            let _ = output_tx.send(runnable(ctx));
         })).is_err() {
             panic!("Failed to send operation to be run on main thread");
         }
@@ -1,179 +1,751 @@
 // Code for the bluetooth client implementation
+//
+// The client is written against BleBackend/BleCentral/BlePeripheral, a small slice of what
+// btleplug offers. btleplug implements them for real hardware, mock_ble.rs for running the
+// whole connection lifecycle without a glove.
 
 use bevy::app::{App, Plugin, Startup};
-use bevy::ecs::system::ResMut;
-use btleplug::api::{Central, Characteristic, Manager as _, Peripheral, ScanFilter};
-use btleplug::platform::Manager;
+use bevy::ecs::component::Component;
+use bevy::ecs::system::{Res, ResMut};
+use bevy::log::{info, warn};
+use btleplug::api::{Central, CentralEvent, CharPropFlags, Characteristic, Manager as _, Peripheral, PeripheralProperties, ScanFilter, ValueNotification, WriteType};
+use btleplug::platform::{Adapter, Manager, Peripheral as PlatformPeripheral, PeripheralId};
+use futures::{FutureExt, Stream, StreamExt};
+use std::future::{self, Future};
+use std::pin::Pin;
 use std::time::Duration;
+use tokio::sync::{mpsc, watch};
 use tokio::time;
+use uuid::Uuid;
 
-use crate::asyncs::{TaskContext, TokioTasksPlugin, TokioTasksRuntime};
-use crate::ruka::RukaInput;
+use crate::asyncs::{TaskContext, TokioTasksRuntime};
+use crate::command::Command;
+use crate::config::{GloveConfig, GloveDevice, GloveSource};
+use crate::mock_ble::MockBleBackend;
+use crate::device::{BATTERY_LEVEL_UUID, DEVICE_INFO_UUIDS};
+use crate::error::GloveError;
+use crate::picker::{DevicePicker, ScanResults, ScannedDevice};
+use crate::ruka::GloveConnection;
+use crate::schema::GloveSchema;
+use crate::transport::{spawn_transport, GloveSample, GloveSink, GloveTransport, SampleQueue};
+
+/// Delay before the first reconnection attempt, doubled after every failed attempt.
+const RETRY_BACKOFF_MIN: Duration = Duration::from_secs(1);
+const RETRY_BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+/// How often characteristics that don't support notify are read.
+const POLL_INTERVAL: Duration = Duration::from_millis(1000 / 60);
+/// How often the battery level is read, for gloves that can't notify it.
+const BATTERY_POLL_INTERVAL: Duration = Duration::from_secs(60);
+/// How often the signal strength is read for the link diagnostics.
+const RSSI_INTERVAL: Duration = Duration::from_secs(1);
+
+pub type BleResult<T> = Result<T, btleplug::Error>;
+pub type BleEvents<Id> = Pin<Box<dyn Stream<Item = BleEvent<Id>> + Send>>;
+pub type Notifications = Pin<Box<dyn Stream<Item = ValueNotification> + Send>>;
 
 pub struct BLEPlugin;
 
 impl Plugin for BLEPlugin {
     fn build(&self, app: &mut App) {
         app
-            .add_plugins(TokioTasksPlugin::default())
             .add_systems(Startup, connect)
         ;
     }
 }
 
-fn connect(runtime: ResMut<TokioTasksRuntime>) {
+fn connect(
+    runtime: ResMut<TokioTasksRuntime>,
+    queue: Res<SampleQueue>,
+    config: Res<GloveConfig>,
+    schema: Res<GloveSchema>,
+    picker: Res<DevicePicker>,
+) {
     // do the bluetooth connection thingy
-    runtime.spawn_background_task(try_connect);
+    // One task per glove, each with its own connection lifecycle
+    for device in config.gloves.iter() {
+        match device.source {
+            GloveSource::Ble => spawn_transport(&runtime, &queue, device, &schema, BleTransport::new(BtleplugBackend, &config, device)),
+            GloveSource::MockBle => {
+                let backend = MockBleBackend::new(config.mock_ble.clone(), schema.clone());
+                spawn_transport(&runtime, &queue, device, &schema, BleTransport::new(backend, &config, device));
+            }
+            _ => {}
+        }
+    }
+
+    // Plus one for the device picker, so it can show everything in range
+    let adapter_index = config.adapter_index;
+    let scan_requests = picker.scan_requests();
+    if config.gloves.iter().any(|device| device.source == GloveSource::Ble) {
+        runtime.spawn_background_task(move |ctx| scan_for_picker(ctx, BtleplugBackend, adapter_index, scan_requests));
+    } else if config.gloves.iter().any(|device| device.source == GloveSource::MockBle) {
+        let backend = MockBleBackend::new(config.mock_ble.clone(), schema.clone());
+        runtime.spawn_background_task(move |ctx| scan_for_picker(ctx, backend, adapter_index, scan_requests));
+    }
+}
+
+/// Where Bluetooth adapters come from.
+pub trait BleBackend: Send + Sync + 'static {
+    type Central: BleCentral;
+
+    /// Short name for logging, e.g. "BLE".
+    fn name(&self) -> &'static str;
+
+    fn open(&self, adapter_index: usize) -> impl Future<Output = Result<Self::Central, GloveError>> + Send;
+}
+
+/// What the client needs from a Bluetooth adapter.
+pub trait BleCentral: Send + Sync + 'static {
+    type Id: Clone + PartialEq + Send + Sync + 'static;
+    type Peripheral: BlePeripheral<Id = Self::Id>;
+
+    fn events(&self) -> impl Future<Output = BleResult<BleEvents<Self::Id>>> + Send;
+    fn start_scan(&self, filter: ScanFilter) -> impl Future<Output = BleResult<()>> + Send;
+    fn stop_scan(&self) -> impl Future<Output = BleResult<()>> + Send;
+    /// Every peripheral the adapter has seen so far.
+    fn peripherals(&self) -> impl Future<Output = BleResult<Vec<Self::Peripheral>>> + Send;
+    fn peripheral(&self, id: &Self::Id) -> impl Future<Output = BleResult<Self::Peripheral>> + Send;
+}
+
+/// What the client needs from a device the adapter has seen.
+pub trait BlePeripheral: Clone + Send + Sync + 'static {
+    type Id;
+
+    fn id(&self) -> Self::Id;
+    fn address(&self) -> String;
+    /// None until the peripheral has advertised at least once.
+    fn properties(&self) -> impl Future<Output = BleResult<Option<PeripheralProperties>>> + Send;
+    fn is_connected(&self) -> impl Future<Output = BleResult<bool>> + Send;
+    fn connect(&self) -> impl Future<Output = BleResult<()>> + Send;
+    fn disconnect(&self) -> impl Future<Output = BleResult<()>> + Send;
+    fn discover_services(&self) -> impl Future<Output = BleResult<()>> + Send;
+    /// Only filled in once services have been discovered.
+    fn characteristics(&self) -> Vec<Characteristic>;
+    fn subscribe(&self, characteristic: &Characteristic) -> impl Future<Output = BleResult<()>> + Send;
+    fn unsubscribe(&self, characteristic: &Characteristic) -> impl Future<Output = BleResult<()>> + Send;
+    fn read(&self, characteristic: &Characteristic) -> impl Future<Output = BleResult<Vec<u8>>> + Send;
+    fn write(&self, characteristic: &Characteristic, data: &[u8], write_type: WriteType) -> impl Future<Output = BleResult<()>> + Send;
+    /// Every notification from a subscribed characteristic. Ends when the peripheral goes away.
+    fn notifications(&self) -> impl Future<Output = BleResult<Notifications>> + Send;
+}
+
+/// The adapter events the client cares about.
+#[derive(Debug, Clone)]
+pub enum BleEvent<Id> {
+    Discovered(Id),
+    Updated(Id),
+    Disconnected(Id),
+}
+
+/// Sent by the device picker to point a glove's BLE task at a different device.
+#[derive(Debug, Clone)]
+pub enum BleControl {
+    /// Drop the current device, if any, and connect to the one at this address instead
+    Connect { address: String },
+    /// Drop the current device and wait for the next Connect
+    Disconnect,
+}
+
+/// Where the device picker sends BleControl messages for a glove. Added by its BLE task.
+#[derive(Component)]
+pub struct BleControlQueue(pub mpsc::UnboundedSender<BleControl>);
 
+/// Streams one glove over Bluetooth LE, reconnecting whenever it goes away.
+pub struct BleTransport<B: BleBackend> {
+    backend: B,
+    config: GloveConfig,
+    device: GloveDevice,
 }
 
-async fn try_connect(mut ctx: TaskContext) {
-    let manager = Manager::new().await.expect("Failed to create BLE manager");
-    let adapter_list = manager.adapters().await.expect("Failed to get adapter list");
-    if adapter_list.is_empty() {
-        eprintln!("No Bluetooth adapters found");
-        return;
+impl<B: BleBackend> BleTransport<B> {
+    pub fn new(backend: B, config: &GloveConfig, device: &GloveDevice) -> Self {
+        Self {
+            backend,
+            config: config.clone(),
+            device: device.clone(),
+        }
     }
+}
 
-    for adapter in adapter_list.iter() {
-        println!("Starting scan on {}...", adapter.adapter_info().await.expect("Failed to get adapter info"));
-        
-        
-        adapter
-            .start_scan(ScanFilter::default())
-            .await
-            .expect("Can't scan BLE adapter for connected devices...");
+impl<B: BleBackend> GloveTransport for BleTransport<B> {
+    fn name(&self) -> String {
+        String::from(self.backend.name())
+    }
+
+    async fn run(self, sink: GloveSink) {
+        try_connect(sink, self.backend, self.config, self.device).await
+    }
 
+    fn handles_shutdown(&self) -> bool {
+        // Otherwise the glove can stay connected to the OS after the app is gone
+        true
+    }
+}
+
+/// A peripheral the client is connected to and what it subscribed to, so both can be let go of.
+struct Connected<P> {
+    peripheral: P,
+    subscribed: Vec<Characteristic>,
+}
 
-        time::sleep(Duration::from_secs(20)).await;
-        let peripherals = adapter.peripherals().await.expect("Failed to get peripherals");
-        if peripherals.is_empty() {
-            eprintln!("->>> BLE peripheral devices were not found, sorry. Exiting...");
+impl<P: BlePeripheral> Connected<P> {
+    /// Unsubscribes from everything and disconnects. Errors are only logged, since the glove
+    /// is being let go of either way.
+    async fn release(self) {
+        for characteristic in self.subscribed.iter() {
+            if let Err(err) = self.peripheral.unsubscribe(characteristic).await {
+                warn!("Error unsubscribing from {}: {}", characteristic.uuid, err);
+            }
+        }
+        if let Err(err) = self.peripheral.disconnect().await {
+            warn!("Error disconnecting: {}", err);
+        }
+    }
+}
+
+async fn try_connect<B: BleBackend>(mut sink: GloveSink, backend: B, config: GloveConfig, mut device: GloveDevice) {
+    let shutdown = sink.shutdown().clone();
+    let mut commands = sink.accept_commands().await;
+    let (control_tx, mut controls) = mpsc::unbounded_channel();
+    sink.insert_component(BleControlQueue(control_tx)).await;
+    let mut backoff = RETRY_BACKOFF_MIN;
+
+    // No adapter is not fatal, one may still be plugged in
+    let adapter = loop {
+        match backend.open(config.adapter_index).await {
+            Ok(adapter) => break adapter,
+            Err(err) => {
+                sink.set_connection(GloveConnection::Disconnected).await;
+                sink.report_error(err).await;
+            }
+        }
+        info!("Looking for a Bluetooth adapter again in {:?}...", backoff);
+        tokio::select! {
+            _ = time::sleep(backoff) => {}
+            _ = shutdown.cancelled() => return,
+        }
+        backoff = (backoff * 2).min(RETRY_BACKOFF_MAX);
+    };
+
+    backoff = RETRY_BACKOFF_MIN;
+    let mut paused = false;
+    loop {
+        let mut control = None;
+        if paused {
+            control = tokio::select! {
+                control = controls.recv() => control,
+                _ = shutdown.cancelled() => None,
+            };
+            if control.is_none() {
+                return;
+            }
         } else {
+            // The picker or the app exiting can take over at any point of a session
+            let mut connected = None;
+            let session = tokio::select! {
+                result = run_session(&mut sink, &mut commands, &mut connected, &adapter, &config, &device) => Some(result),
+                Some(picked) = controls.recv() => {
+                    control = Some(picked);
+                    None
+                }
+                _ = shutdown.cancelled() => None,
+            };
+            // However the session ended, the link may still be up
+            if let Some(connected) = connected {
+                connected.release().await;
+            }
+            if shutdown.is_cancelled() {
+                info!("{:?} glove let go of for shutdown", device.hand);
+                return;
+            }
+
+            sink.set_connection(GloveConnection::Disconnected).await;
+
+            match session {
+                // A session that got as far as streaming counts as a success, so start the backoff over.
+                Some(Ok(())) => backoff = RETRY_BACKOFF_MIN,
+                Some(Err(err)) => sink.report_error(err).await,
+                None => {}
+            }
+            if control.is_none() {
+                info!("Retrying in {:?}...", backoff);
+                tokio::select! {
+                    _ = time::sleep(backoff) => {}
+                    Some(picked) = controls.recv() => control = Some(picked),
+                    _ = shutdown.cancelled() => return,
+                }
+                backoff = (backoff * 2).min(RETRY_BACKOFF_MAX);
+            }
+        }
+
+        match control {
+            Some(BleControl::Connect { address }) => {
+                info!("Switching {:?} glove to {}", device.hand, address);
+                device.address = Some(address);
+                device.name = None;
+                backoff = RETRY_BACKOFF_MIN;
+                paused = false;
+            }
+            Some(BleControl::Disconnect) => {
+                info!("{:?} glove disconnected from the device picker", device.hand);
+                paused = true;
+            }
+            None => {}
+        }
+    }
+}
+
+/// Runs one pass through the connection lifecycle: scan, connect, discover services and stream
+/// until the glove goes away. Returns Ok if the glove made it to streaming. The peripheral is
+/// left in `connected` once connected, for the caller to let go of however the session ends.
+async fn run_session<C: BleCentral>(
+    sink: &mut GloveSink,
+    commands: &mut mpsc::UnboundedReceiver<Command>,
+    connected: &mut Option<Connected<C::Peripheral>>,
+    adapter: &C,
+    config: &GloveConfig,
+    device: &GloveDevice,
+) -> Result<(), GloveError> {
+    let mut events = adapter.events().await.map_err(GloveError::Adapter)?;
 
-            let target_name = "Ruka";
+    sink.set_connection(GloveConnection::Scanning).await;
+    info!("Scanning for {}...", device.target());
+    let filter = ScanFilter {
+        services: config.service_uuid.into_iter().collect(),
+    };
+    adapter.start_scan(filter).await.map_err(GloveError::Scan)?;
+
+    let found = time::timeout(config.scan_timeout(), find_glove(adapter, &mut events, device)).await;
+    let _ = adapter.stop_scan().await;
+    let peripheral = found.ok().flatten().ok_or_else(|| GloveError::NotFound {
+        target: device.target(),
+        timeout: config.scan_timeout(),
+    })?;
+
+    sink.set_connection(GloveConnection::Connecting).await;
+    let is_connected = peripheral.is_connected().await.unwrap_or(false);
+    if !is_connected {
+        info!("Connecting to peripheral {}...", device.target());
+        peripheral.connect().await.map_err(GloveError::Connect)?;
+    }
+    info!("Now connected to peripheral {}...", device.target());
+    let subscribed = &mut connected.insert(Connected { peripheral: peripheral.clone(), subscribed: Vec::new() }).subscribed;
+    let address = peripheral.address();
+    sink.update_device_info(move |info| info.new_session(Some(address))).await;
 
-            // All peripheral devices in range
-            for peripheral in peripherals.iter() {
-                let properties = peripheral.properties().await.expect("Failed to get peripheral properties");
-                let is_connected = peripheral.is_connected().await.expect("Failed to get connection status");
-                let local_name = properties
-                    .unwrap()
-                    .local_name
-                    .unwrap_or(String::from("(peripheral name unknown)"));
+    sink.set_connection(GloveConnection::DiscoveringServices).await;
+    info!("Discover peripheral {} services...", device.target());
+    peripheral.discover_services().await.map_err(GloveError::DiscoverServices)?;
 
-                if local_name != target_name {
+    // Subscribe to the data characteristics. Anything that can't notify gets polled instead.
+    let mut polled: Vec<Characteristic> = Vec::new();
+    for characteristic in peripheral.characteristics() {
+        if sink.schema().by_uuid(characteristic.uuid).is_none() {
+            continue;
+        }
+        if characteristic.properties.contains(CharPropFlags::NOTIFY) {
+            match peripheral.subscribe(&characteristic).await {
+                Ok(()) => {
+                    info!("Subscribed to characteristic {}", characteristic.uuid);
+                    subscribed.push(characteristic);
                     continue;
                 }
+                Err(err) => {
+                    warn!("Error subscribing to {}, polling instead: {}", characteristic.uuid, err);
+                }
+            }
+        }
+        info!("Polling characteristic {} every {:?}", characteristic.uuid, POLL_INTERVAL);
+        polled.push(characteristic);
+    }
+
+    let mut notifications = peripheral.notifications().await.map_err(GloveError::Notifications)?;
+    let mut poll_timer = time::interval(POLL_INTERVAL);
 
-                println!(
-                    "Peripheral {:?} is connected: {:?}",
-                    local_name, is_connected
-                );
-                if !is_connected {
-                    println!("Connecting to peripheral {:?}...", &local_name);
-                    if let Err(err) = peripheral.connect().await {
-                        eprintln!("Error connecting to peripheral, skipping: {}", err);
-                        continue;
+    // Battery and Device Information, for gloves that have them. Battery changes are
+    // subscribed to where possible and polled otherwise.
+    let mut device_info: Vec<(Uuid, Vec<u8>)> = Vec::new();
+    let mut battery_polled: Option<Characteristic> = None;
+    for characteristic in peripheral.characteristics() {
+        if !DEVICE_INFO_UUIDS.contains(&characteristic.uuid) {
+            continue;
+        }
+        match peripheral.read(&characteristic).await {
+            Ok(value) => device_info.push((characteristic.uuid, value)),
+            Err(err) => warn!("Error reading characteristic {}: {}", characteristic.uuid, err),
+        }
+        if characteristic.uuid != BATTERY_LEVEL_UUID {
+            continue;
+        }
+        let notifies = characteristic.properties.contains(CharPropFlags::NOTIFY)
+            && peripheral.subscribe(&characteristic).await.is_ok();
+        match notifies {
+            true => subscribed.push(characteristic),
+            false => battery_polled = Some(characteristic),
+        }
+    }
+    sink.update_device_info(move |info| {
+        for (uuid, value) in device_info {
+            info.update(uuid, &value);
+        }
+    }).await;
+    let mut battery_timer = time::interval(BATTERY_POLL_INTERVAL);
+    let mut rssi_timer = time::interval(RSSI_INTERVAL);
+
+    // Whatever was sent while the glove was away is stale by now
+    while let Ok(command) = commands.try_recv() {
+        sink.command_failed(command, String::from("the glove was not connected")).await;
+    }
+
+    sink.set_connection(GloveConnection::Streaming).await;
+
+    let glove_id = peripheral.id();
+    loop {
+        let mut samples: Vec<GloveSample> = Vec::new();
+        let mut battery: Option<Vec<u8>> = None;
+
+        tokio::select! {
+            notification = notifications.next() => match notification {
+                Some(notification) if notification.uuid == BATTERY_LEVEL_UUID => battery = Some(notification.value),
+                Some(notification) => samples.extend(decode_packet(sink, notification.uuid, &notification.value)),
+                None => {
+                    info!("Notification stream from {} ended", device.target());
+                    break;
+                }
+            },
+            event = events.next() => match event {
+                Some(BleEvent::Disconnected(id)) if id == glove_id => {
+                    info!("Disconnected from peripheral {}", device.target());
+                    break;
+                }
+                Some(_) => {}
+                None => break,
+            },
+            Some(command) = commands.recv() => {
+                if let Err(err) = write_command(&peripheral, sink.schema(), &command).await {
+                    sink.command_failed(command, err).await;
+                }
+            }
+            _ = poll_timer.tick(), if !polled.is_empty() => {
+                for characteristic in polled.iter() {
+                    match peripheral.read(characteristic).await {
+                        Ok(data) => samples.extend(decode_packet(sink, characteristic.uuid, &data)),
+                        Err(err) => warn!("Error reading characteristic: {}", err),
                     }
                 }
-                let is_connected = peripheral.is_connected().await.expect("Failed to get connection status");
-                
-                println!(
-                    "Now connected ({:?}) to peripheral {:?}...",
-                    is_connected, &local_name
-                );
-
-                println!("Discover peripheral {:?} services...", &local_name);
-                peripheral.discover_services().await.expect("Failed to discover services");
-
-                ctx.run_on_main_thread(move |main_ctx| {
-                    let mut ruka = main_ctx.world.get_resource_mut::<RukaInput>().unwrap();
-                    ruka.set_init(true);
-                }).await;
-
-                while is_connected {
-                    // if !peripheral.is_connected().await.unwrap() {
-                    //     println!("Disconnected from peripheral {:?}...", &local_name);
-                    //     break;
-                    // }
-                    let mut flexvalues: [u16; 5] = [0; 5];
-                    let mut imuvalues: [f32; 6] = [0.0; 6];
-
-                    for service in peripheral.services() {
-                        // println!(
-                        //     "Service UUID {}, primary: {}",
-                        //     service.uuid, service.primary
-                        // );
-                        for characteristic in service.characteristics {
-                            if characteristic.uuid.to_string() == "00002af9-0000-1000-8000-00805f9b34fb" {
-                                // println!("Trying to read {:?}", characteristic.uuid.to_string());
-                                let read_result = peripheral.read(&characteristic).await;
-                                match read_result {
-                                    Ok(data) => {
-                                        if data.len() == 10 {
-                                            for i in 0..5 {
-                                                let high_byte = data[2*i];
-                                                let low_byte = data[2*i+1];
-    
-                                                flexvalues[i] = ((high_byte as u16) << 8) | (low_byte as u16);
-                                            }
-    
-                                        }
-                                    }
-                                    Err(err) => {
-                                        eprintln!("Error reading characteristic: {}", err);
-                                    }
-                                }
-                                
-                            } else if characteristic.uuid.to_string() == "00002713-0000-1000-8000-00805f9b34fb" {
-                                // println!("Trying to read {:?}", characteristic.uuid.to_string());
-                                let read_result = peripheral.read(&characteristic).await;
-                                match read_result {
-                                    Ok(data) => {
-                                        if data.len() == 12 {
-                                            for i in 0..6 {
-                                                let high_byte = data[i * 2] as i16;
-                                                let low_byte = data[i * 2 + 1] as i16;
-                                                let int_value = (high_byte << 8) | (low_byte & 0xFF );
-                                                let float_value = int_value as f32 / 100.0;
-                                                imuvalues[i] = float_value;
-                                            }
-                                            
-                                        }
-                                    }
-                                    Err(err) => {
-                                        eprintln!("Error reading characteristic: {}", err);
-                                    }
-                                }
-                            } else {
-                                continue;
-                            }
-                            println!("Read flex bytes: {:?}", flexvalues);
-                            println!("Read imu bytes: {:?}", imuvalues);
-                            
-                        }
+            }
+            _ = battery_timer.tick(), if battery_polled.is_some() => {
+                if let Some(characteristic) = battery_polled.as_ref() {
+                    match peripheral.read(characteristic).await {
+                        Ok(value) => battery = Some(value),
+                        Err(err) => warn!("Error reading battery level: {}", err),
                     }
+                }
+            }
+            _ = rssi_timer.tick() => {
+                // Not every platform refreshes this while connected, in which case it stays at
+                // whatever the last advertisement said
+                if let Ok(Some(properties)) = peripheral.properties().await {
+                    if let Some(rssi) = properties.rssi {
+                        sink.set_rssi(rssi);
+                    }
+                }
+            }
+        }
 
-                    let delay = Duration::from_millis(1000 / 60);
-                    // tokio::time::sleep(delay).await;
+        // Grab everything else that has already arrived, so it goes out as one batch
+        while let Some(Some(notification)) = notifications.next().now_or_never() {
+            if notification.uuid == BATTERY_LEVEL_UUID {
+                battery = Some(notification.value);
+                continue;
+            }
+            samples.extend(decode_packet(sink, notification.uuid, &notification.value));
+        }
+
+        if let Some(value) = battery {
+            sink.update_device_info(move |info| {
+                info.update(BATTERY_LEVEL_UUID, &value);
+            }).await;
+        }
+        sink.send(samples).await;
+    }
+
+    Ok(())
+}
+
+/// Scans while the device picker is open and passes everything it sees on to it, until the app exits.
+async fn scan_for_picker<B: BleBackend>(ctx: TaskContext, backend: B, adapter_index: usize, scan_requests: watch::Receiver<bool>) {
+    // Scans end along with the process, so there's nothing to clean up
+    let shutdown = ctx.shutdown_token();
+    tokio::select! {
+        _ = scan_devices(ctx, backend, adapter_index, scan_requests) => {}
+        _ = shutdown.cancelled() => {}
+    }
+}
+
+async fn scan_devices<B: BleBackend>(mut ctx: TaskContext, backend: B, adapter_index: usize, mut scan_requests: watch::Receiver<bool>) {
+    // Adapter errors are already reported by the glove tasks
+    let adapter = loop {
+        if let Ok(adapter) = backend.open(adapter_index).await {
+            break adapter;
+        }
+        time::sleep(RETRY_BACKOFF_MAX).await;
+    };
+    let mut events = match adapter.events().await {
+        Ok(events) => events,
+        Err(err) => {
+            warn!("No scan results for the device picker: {}", err);
+            return;
+        }
+    };
 
-                    if is_connected {
-                        ctx.run_on_main_thread(move |main_ctx| {
-                            let mut ruka = main_ctx.world.get_resource_mut::<RukaInput>().unwrap();
-                            ruka.update_fingers(flexvalues);
-                            ruka.update_imu(imuvalues);
-                        }).await;
+    loop {
+        if scan_requests.wait_for(|open| *open).await.is_err() {
+            return;
+        }
+        if let Err(err) = adapter.start_scan(ScanFilter::default()).await {
+            warn!("Error scanning for the device picker: {}", err);
+            time::sleep(RETRY_BACKOFF_MAX).await;
+            continue;
+        }
+
+        loop {
+            let mut seen = Vec::new();
+            tokio::select! {
+                changed = scan_requests.changed() => {
+                    if changed.is_err() || !*scan_requests.borrow() {
+                        break;
                     }
                 }
-                // if is_connected {
-                //     // println!("Disconnecting from peripheral {:?}...", &local_name);
-                //     // peripheral
-                //     //     .disconnect()
-                //     //     .await
-                //     //     .expect("Error disconnecting from BLE peripheral");
-                // }
+                event = events.next() => match event {
+                    Some(event) => seen.extend(discovered_id(event)),
+                    None => return,
+                },
+            }
+            while let Some(Some(event)) = events.next().now_or_never() {
+                seen.extend(discovered_id(event));
+            }
+            seen.dedup();
+
+            let mut devices = Vec::new();
+            for id in seen {
+                let Ok(peripheral) = adapter.peripheral(&id).await else {
+                    continue;
+                };
+                if let Ok(Some(properties)) = peripheral.properties().await {
+                    devices.push(ScannedDevice {
+                        address: peripheral.address(),
+                        name: properties.local_name,
+                        rssi: properties.rssi,
+                        services: properties.services,
+                    });
+                }
+            }
+            if devices.is_empty() {
+                continue;
             }
+            ctx.run_on_main_thread(move |main_ctx| {
+                let mut results = main_ctx.world.resource_mut::<ScanResults>();
+                for device in devices {
+                    results.update(device);
+                }
+            }).await;
         }
+        let _ = adapter.stop_scan().await;
+    }
+}
+
+fn discovered_id<Id>(event: BleEvent<Id>) -> Option<Id> {
+    match event {
+        BleEvent::Discovered(id) | BleEvent::Updated(id) => Some(id),
+        BleEvent::Disconnected(_) => None,
+    }
+}
+
+/// Looks through the peripherals the adapter already knows about, then waits for new
+/// advertisements until one of them turns out to be the glove.
+async fn find_glove<C: BleCentral>(adapter: &C, events: &mut BleEvents<C::Id>, device: &GloveDevice) -> Option<C::Peripheral> {
+    for peripheral in adapter.peripherals().await.unwrap_or_default() {
+        if is_glove(&peripheral, device).await {
+            return Some(peripheral);
+        }
+    }
+
+    while let Some(event) = events.next().await {
+        let Some(id) = discovered_id(event) else {
+            continue;
+        };
+        let Ok(peripheral) = adapter.peripheral(&id).await else {
+            continue;
+        };
+        if is_glove(&peripheral, device).await {
+            return Some(peripheral);
+        }
+    }
+    None
+}
+
+async fn is_glove<P: BlePeripheral>(peripheral: &P, device: &GloveDevice) -> bool {
+    // Properties are None until the peripheral has advertised at least once
+    let local_name = peripheral
+        .properties()
+        .await
+        .ok()
+        .flatten()
+        .and_then(|properties| properties.local_name);
+
+    device.matches(local_name.as_deref(), &peripheral.address())
+}
+
+/// Writes a command to its characteristic on the glove.
+async fn write_command<P: BlePeripheral>(peripheral: &P, schema: &GloveSchema, command: &Command) -> Result<(), String> {
+    let uuid = schema
+        .commands
+        .uuid(command)
+        .ok_or_else(|| String::from("the schema has no characteristic for it"))?;
+    let characteristic = peripheral
+        .characteristics()
+        .into_iter()
+        .find(|characteristic| characteristic.uuid == uuid)
+        .ok_or_else(|| format!("the glove has no characteristic {}", uuid))?;
+
+    let write_type = match command.needs_response() {
+        true => WriteType::WithResponse,
+        false => WriteType::WithoutResponse,
+    };
+    peripheral
+        .write(&characteristic, &command.encode(), write_type)
+        .await
+        .map_err(|err| err.to_string())
+}
+
+/// Maps a characteristic's payload to the sample it carries, if it is one of ours.
+fn decode_packet(sink: &mut GloveSink, uuid: Uuid, data: &[u8]) -> Option<GloveSample> {
+    let decoded = sink.schema().by_uuid(uuid)?.decode(data);
+    match decoded {
+        Ok(data) => Some(sink.sample(data)),
+        Err(err) => {
+            warn!("Dropping packet from {}: {}", uuid, err);
+            sink.decode_failed();
+            None
+        }
+    }
+}
+
+/// Real adapters, through btleplug.
+struct BtleplugBackend;
+
+impl BleBackend for BtleplugBackend {
+    type Central = Adapter;
+
+    fn name(&self) -> &'static str {
+        "BLE"
     }
-}
\ No newline at end of file
+
+    async fn open(&self, adapter_index: usize) -> Result<Adapter, GloveError> {
+        let manager = Manager::new().await.map_err(GloveError::Manager)?;
+        let adapter = manager
+            .adapters()
+            .await
+            .map_err(GloveError::Adapter)?
+            .into_iter()
+            .nth(adapter_index)
+            .ok_or(GloveError::NoAdapter { index: adapter_index })?;
+
+        match adapter.adapter_info().await {
+            Ok(adapter_info) => info!("Using adapter {}", adapter_info),
+            Err(err) => warn!("Using adapter {}, which can't describe itself: {}", adapter_index, err),
+        }
+        Ok(adapter)
+    }
+}
+
+impl BleCentral for Adapter {
+    type Id = PeripheralId;
+    type Peripheral = PlatformPeripheral;
+
+    async fn events(&self) -> BleResult<BleEvents<PeripheralId>> {
+        let events = Central::events(self).await?.filter_map(|event| {
+            future::ready(match event {
+                CentralEvent::DeviceDiscovered(id) => Some(BleEvent::Discovered(id)),
+                CentralEvent::DeviceUpdated(id) => Some(BleEvent::Updated(id)),
+                CentralEvent::DeviceDisconnected(id) => Some(BleEvent::Disconnected(id)),
+                _ => None,
+            })
+        });
+        Ok(Box::pin(events))
+    }
+
+    async fn start_scan(&self, filter: ScanFilter) -> BleResult<()> {
+        Central::start_scan(self, filter).await
+    }
+
+    async fn stop_scan(&self) -> BleResult<()> {
+        Central::stop_scan(self).await
+    }
+
+    async fn peripherals(&self) -> BleResult<Vec<PlatformPeripheral>> {
+        Central::peripherals(self).await
+    }
+
+    async fn peripheral(&self, id: &PeripheralId) -> BleResult<PlatformPeripheral> {
+        Central::peripheral(self, id).await
+    }
+}
+
+impl BlePeripheral for PlatformPeripheral {
+    type Id = PeripheralId;
+
+    fn id(&self) -> PeripheralId {
+        Peripheral::id(self)
+    }
+
+    fn address(&self) -> String {
+        Peripheral::address(self).to_string()
+    }
+
+    async fn properties(&self) -> BleResult<Option<PeripheralProperties>> {
+        Peripheral::properties(self).await
+    }
+
+    async fn is_connected(&self) -> BleResult<bool> {
+        Peripheral::is_connected(self).await
+    }
+
+    async fn connect(&self) -> BleResult<()> {
+        Peripheral::connect(self).await
+    }
+
+    async fn disconnect(&self) -> BleResult<()> {
+        Peripheral::disconnect(self).await
+    }
+
+    async fn discover_services(&self) -> BleResult<()> {
+        Peripheral::discover_services(self).await
+    }
+
+    fn characteristics(&self) -> Vec<Characteristic> {
+        Peripheral::characteristics(self).into_iter().collect()
+    }
+
+    async fn subscribe(&self, characteristic: &Characteristic) -> BleResult<()> {
+        Peripheral::subscribe(self, characteristic).await
+    }
+
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> BleResult<()> {
+        Peripheral::unsubscribe(self, characteristic).await
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> BleResult<Vec<u8>> {
+        Peripheral::read(self, characteristic).await
+    }
+
+    async fn write(&self, characteristic: &Characteristic, data: &[u8], write_type: WriteType) -> BleResult<()> {
+        Peripheral::write(self, characteristic, data, write_type).await
+    }
+
+    async fn notifications(&self) -> BleResult<Notifications> {
+        Peripheral::notifications(self).await
+    }
+}
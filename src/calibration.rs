@@ -0,0 +1,340 @@
+// Guided calibration of the flex sensors, started with K for the glove shown in the overlay.
+//
+// The wearer holds their hand open, makes a fist and spreads their fingers, a few seconds
+// each. Each finger's range comes from the median of those captures rather than the extremes,
+// so a spike doesn't ruin it, and how far apart the poses are compared to how much the
+// readings wandered while holding still gives a quality score. The gyroscope's bias is
+// measured while the hand is held open. Shift+K throws the calibration away and goes back to
+// learning the ranges from whatever comes in.
+
+use bevy::app::{App, Plugin, Update};
+use bevy::ecs::event::{Event, EventReader, EventWriter};
+use bevy::ecs::system::{Query, Res, ResMut, Resource};
+use bevy::input::{keyboard::KeyCode, ButtonInput};
+use bevy::log::info;
+use bevy::math::Vec3;
+use bevy::time::Time;
+use bevy_inspector_egui::bevy_egui::{egui, EguiContexts, EguiPlugin};
+
+use crate::ruka::{ActiveHands, Handedness, RukaInput};
+use crate::transport::GloveSample;
+
+/// Seconds to get into a pose before capturing starts.
+const SETTLE_SECS: f32 = 1.5;
+/// Seconds each pose is captured for.
+const HOLD_SECS: f32 = 3.0;
+/// Fewer samples than this in a pose means the glove wasn't streaming.
+const MIN_SAMPLES: usize = 20;
+/// How much the readings may wander while holding still, relative to the range, before the
+/// quality drops to one half.
+const NOISE_WEIGHT: f32 = 4.0;
+
+pub struct CalibrationPlugin;
+
+impl Plugin for CalibrationPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<EguiPlugin>() {
+            app.add_plugins(EguiPlugin);
+        }
+        app
+            .insert_resource(Calibration::default())
+            .add_event::<CalibrationChanged>()
+            .add_systems(Update, calibration_keys)
+            .add_systems(Update, capture_calibration)
+            .add_systems(Update, show_calibration)
+        ;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalibrationPose {
+    Open,
+    Fist,
+    Spread,
+}
+
+impl CalibrationPose {
+    const ALL: [CalibrationPose; 3] = [CalibrationPose::Open, CalibrationPose::Fist, CalibrationPose::Spread];
+
+    fn prompt(&self) -> &'static str {
+        match self {
+            CalibrationPose::Open => "Hold your hand open, fingers together",
+            CalibrationPose::Fist => "Make a fist",
+            CalibrationPose::Spread => "Spread your fingers as far as they go",
+        }
+    }
+}
+
+/// Sent when a glove has been calibrated or its calibration was reset.
+#[derive(Event)]
+pub struct CalibrationChanged {
+    pub hand: Handedness,
+}
+
+/// The guided calibration in progress, or how the last one went.
+#[derive(Resource, Default)]
+pub struct Calibration {
+    run: Option<CalibrationRun>,
+    result: Option<Result<CalibrationResult, String>>,
+}
+
+struct CalibrationRun {
+    hand: Handedness,
+    /// Index into `CalibrationPose::ALL`
+    step: usize,
+    /// Seconds since the current pose was prompted
+    elapsed: f32,
+    /// Flex readings of every sample captured, per pose
+    captures: Vec<Vec<Vec<f32>>>,
+    /// Gyroscope readings captured while the hand was held open
+    gyro: Vec<Vec3>,
+}
+
+impl CalibrationRun {
+    fn new(hand: Handedness) -> Self {
+        Self {
+            hand,
+            step: 0,
+            elapsed: 0.0,
+            captures: vec![Vec::new(); CalibrationPose::ALL.len()],
+            gyro: Vec::new(),
+        }
+    }
+
+    fn pose(&self) -> CalibrationPose {
+        CalibrationPose::ALL[self.step]
+    }
+
+    fn capturing(&self) -> bool {
+        self.elapsed >= SETTLE_SECS
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CalibrationResult {
+    pub hand: Handedness,
+    /// (flexed, straight) reading of each finger
+    pub limits: Vec<(u16, u16)>,
+    /// 0 to 1 per finger, see `NOISE_WEIGHT`
+    pub quality: Vec<f32>,
+    /// Average gyroscope reading at rest, if the glove has one
+    pub gyro_bias: Option<Vec3>,
+}
+
+impl CalibrationResult {
+    /// Only as good as the worst finger.
+    pub fn overall_quality(&self) -> f32 {
+        self.quality.iter().copied().fold(1.0, f32::min)
+    }
+
+    /// Works out the ranges from one capture per pose, in `CalibrationPose::ALL` order.
+    fn compute(hand: Handedness, fingers: usize, captures: &[Vec<Vec<f32>>], gyro: &[Vec3]) -> Result<Self, String> {
+        for (pose, capture) in CalibrationPose::ALL.iter().zip(captures) {
+            if capture.len() < MIN_SAMPLES {
+                return Err(format!("Only got {} samples while capturing {:?}, is the glove streaming?", capture.len(), pose));
+            }
+        }
+
+        let mut limits = Vec::with_capacity(fingers);
+        let mut quality = Vec::with_capacity(fingers);
+        for finger in 0..fingers {
+            let stats: Vec<(f32, f32)> = captures
+                .iter()
+                .map(|capture| {
+                    let mut values: Vec<f32> = capture.iter().filter_map(|flex| flex.get(finger).copied()).collect();
+                    values.sort_by(f32::total_cmp);
+                    let noise = percentile(&values, 0.9) - percentile(&values, 0.1);
+                    (percentile(&values, 0.5), noise)
+                })
+                .collect();
+            let [(open, open_noise), (fist, fist_noise), (spread, spread_noise)] = stats[..] else {
+                unreachable!("one capture per pose");
+            };
+
+            // Spreading can straighten a finger further than holding the hand open did
+            let straight = match (spread - fist).abs() > (open - fist).abs() {
+                true => spread,
+                false => open,
+            };
+            let separation = (straight - fist).abs();
+            let noise = open_noise.max(fist_noise).max(spread_noise);
+            quality.push(match separation > 0.0 {
+                true => separation / (separation + NOISE_WEIGHT * noise),
+                false => 0.0,
+            });
+            limits.push((fist as u16, straight as u16));
+        }
+
+        let gyro_bias = (!gyro.is_empty()).then(|| gyro.iter().sum::<Vec3>() / gyro.len() as f32);
+        Ok(Self { hand, limits, quality, gyro_bias })
+    }
+}
+
+/// Value at `fraction` of the way through `sorted`.
+pub fn percentile(sorted: &[f32], fraction: f32) -> f32 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let index = ((sorted.len() - 1) as f32 * fraction.clamp(0.0, 1.0)).round() as usize;
+    sorted[index]
+}
+
+fn calibration_keys(
+    keys: Res<ButtonInput<KeyCode>>,
+    hands: Res<ActiveHands>,
+    mut calibration: ResMut<Calibration>,
+    mut gloves: Query<(&Handedness, &mut RukaInput)>,
+    mut changes: EventWriter<CalibrationChanged>,
+) {
+    if !keys.just_pressed(KeyCode::KeyK) {
+        return;
+    }
+    let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    match shift {
+        true => reset_calibration(&mut calibration, &mut gloves, &mut changes, hands.debug),
+        false => start_calibration(&mut calibration, hands.debug),
+    }
+}
+
+fn start_calibration(calibration: &mut Calibration, hand: Handedness) {
+    info!("Calibrating {:?} glove", hand);
+    calibration.run = Some(CalibrationRun::new(hand));
+    calibration.result = None;
+}
+
+fn reset_calibration(
+    calibration: &mut Calibration,
+    gloves: &mut Query<(&Handedness, &mut RukaInput)>,
+    changes: &mut EventWriter<CalibrationChanged>,
+    hand: Handedness,
+) {
+    info!("Resetting calibration of {:?} glove", hand);
+    calibration.run = None;
+    calibration.result = None;
+    if let Some((_, mut ruka)) = gloves.iter_mut().find(|(glove, _)| **glove == hand) {
+        ruka.reset_calibration();
+        changes.send(CalibrationChanged { hand });
+    }
+}
+
+/// Collects the calibrating glove's samples and moves through the poses.
+fn capture_calibration(
+    time: Res<Time>,
+    mut samples: EventReader<GloveSample>,
+    mut calibration: ResMut<Calibration>,
+    mut gloves: Query<(&Handedness, &mut RukaInput)>,
+    mut changes: EventWriter<CalibrationChanged>,
+) {
+    let Some(run) = calibration.run.as_mut() else {
+        samples.clear();
+        return;
+    };
+
+    let capturing = run.capturing();
+    for sample in samples.read() {
+        if !capturing || sample.hand != run.hand {
+            continue;
+        }
+        if !sample.flex.is_empty() {
+            run.captures[run.step].push(sample.flex.clone());
+        }
+        let gyro = sample.imu.as_ref().and_then(|imu| imu.gyro);
+        if let (CalibrationPose::Open, Some(gyro)) = (run.pose(), gyro) {
+            run.gyro.push(gyro);
+        }
+    }
+
+    run.elapsed += time.delta_seconds();
+    if run.elapsed < SETTLE_SECS + HOLD_SECS {
+        return;
+    }
+    run.step += 1;
+    run.elapsed = 0.0;
+    if run.step < CalibrationPose::ALL.len() {
+        return;
+    }
+
+    let run = calibration.run.take().unwrap();
+    let Some((_, mut ruka)) = gloves.iter_mut().find(|(hand, _)| **hand == run.hand) else {
+        return;
+    };
+    let result = CalibrationResult::compute(run.hand, ruka.finger_count(), &run.captures, &run.gyro);
+    match &result {
+        Ok(result) => {
+            info!("Calibrated {:?} glove: {:?}, quality {:.2}", run.hand, result.limits, result.overall_quality());
+            ruka.set_finger_limits(result.limits.clone());
+            if let Some(bias) = result.gyro_bias {
+                ruka.set_gyro_bias(bias);
+            }
+            changes.send(CalibrationChanged { hand: run.hand });
+        }
+        Err(err) => info!("Calibration of {:?} glove failed: {}", run.hand, err),
+    }
+    calibration.result = Some(result);
+}
+
+fn show_calibration(
+    mut contexts: EguiContexts,
+    hands: Res<ActiveHands>,
+    mut calibration: ResMut<Calibration>,
+    mut gloves: Query<(&Handedness, &mut RukaInput)>,
+    mut changes: EventWriter<CalibrationChanged>,
+) {
+    if calibration.run.is_none() && calibration.result.is_none() {
+        return;
+    }
+
+    let mut restart = false;
+    let mut reset = false;
+    let mut close = false;
+    egui::Window::new("Calibration")
+        .anchor(egui::Align2::CENTER_TOP, egui::vec2(0.0, 10.0))
+        .resizable(false)
+        .collapsible(false)
+        .show(contexts.ctx_mut(), |ui| {
+            match (&calibration.run, &calibration.result) {
+                (Some(run), _) => {
+                    ui.label(format!("{:?} glove, pose {} of {}", run.hand, run.step + 1, CalibrationPose::ALL.len()));
+                    ui.heading(run.pose().prompt());
+                    let progress = match run.capturing() {
+                        true => egui::ProgressBar::new((run.elapsed - SETTLE_SECS) / HOLD_SECS).text("Hold still"),
+                        false => egui::ProgressBar::new(0.0).text("Get ready"),
+                    };
+                    ui.add(progress);
+                }
+                (None, Some(Ok(result))) => {
+                    ui.label(format!("{:?} glove calibrated", result.hand));
+                    ui.heading(format!("Quality {:.0}%", result.overall_quality() * 100.0));
+                    for (finger, ((flexed, straight), quality)) in result.limits.iter().zip(result.quality.iter()).enumerate() {
+                        ui.label(format!("Finger {}: {} to {}, {:.0}%", finger, flexed, straight, quality * 100.0));
+                    }
+                    if let Some(bias) = result.gyro_bias {
+                        ui.label(format!("Gyro bias: {:.2} {:.2} {:.2}", bias.x, bias.y, bias.z));
+                    }
+                }
+                (None, Some(Err(err))) => {
+                    ui.colored_label(egui::Color32::RED, err.as_str());
+                }
+                (None, None) => {}
+            }
+
+            ui.horizontal(|ui| {
+                restart = ui.button("Restart (K)").clicked();
+                reset = ui.button("Reset (Shift+K)").clicked();
+                close = calibration.run.is_none() && ui.button("Close").clicked();
+            });
+        });
+
+    let hand = match (&calibration.run, &calibration.result) {
+        (Some(run), _) => run.hand,
+        (None, Some(Ok(result))) => result.hand,
+        _ => hands.debug,
+    };
+    if restart {
+        start_calibration(&mut calibration, hand);
+    } else if reset {
+        reset_calibration(&mut calibration, &mut gloves, &mut changes, hand);
+    } else if close {
+        calibration.result = None;
+    }
+}
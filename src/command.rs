@@ -0,0 +1,178 @@
+// Host to glove commands: haptics, the status LED and sensor settings.
+//
+// Anything can send a GloveCommand event. It is routed to the glove's transport, which
+// writes it to the matching characteristic (see [commands] in schema.toml). Failed writes
+// come back as GloveCommandFailed events.
+//
+// Payloads, all big-endian:
+//   vibrate      pattern u8, duration u16 (ms)
+//   led          r u8, g u8, b u8
+//   imu_range    accel range u8 (g), gyro range u16 (°/s)
+//   sample_rate  rate u16 (Hz)
+
+use std::time::Duration;
+
+use bevy::app::{App, Plugin, Update};
+use bevy::ecs::component::Component;
+use bevy::ecs::event::{Event, EventReader, EventWriter};
+use bevy::ecs::query::{Changed, With};
+use bevy::ecs::system::{Query, Res};
+use bevy::log::warn;
+use serde::{Deserialize, Deserializer};
+use tokio::sync::mpsc;
+
+use crate::config::GloveConfig;
+use crate::ruka::{GloveConnection, Handedness};
+
+pub struct CommandPlugin;
+
+impl Plugin for CommandPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_event::<GloveCommand>()
+            .add_event::<GloveCommandFailed>()
+            .add_systems(Update, send_on_connect)
+            .add_systems(Update, dispatch_commands)
+            .add_systems(Update, log_failed_commands)
+        ;
+    }
+}
+
+/// Asks the glove on `hand` to do something.
+#[derive(Event, Debug, Clone)]
+pub struct GloveCommand {
+    pub hand: Handedness,
+    pub command: Command,
+}
+
+/// A command that could not be delivered, and why.
+#[derive(Event, Debug, Clone)]
+pub struct GloveCommandFailed {
+    pub hand: Handedness,
+    pub command: Command,
+    pub error: String,
+}
+
+/// Can also be written in glove.toml, e.g. `{ vibrate = { pattern = "pulse", duration = 80 } }`
+/// with the duration in milliseconds.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Command {
+    Vibrate {
+        pattern: VibrationPattern,
+        #[serde(deserialize_with = "deserialize_millis")]
+        duration: Duration,
+    },
+    SetLed { r: u8, g: u8, b: u8 },
+    SetImuRange { accel_g: u8, gyro_dps: u16 },
+    SetSampleRate(u16),
+}
+
+/// Vibration motor patterns built into the firmware.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum VibrationPattern {
+    Continuous = 0,
+    Pulse = 1,
+    DoublePulse = 2,
+    Ramp = 3,
+}
+
+fn deserialize_millis<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    u64::deserialize(deserializer).map(Duration::from_millis)
+}
+
+impl Command {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Vibrate { .. } => "vibrate",
+            Command::SetLed { .. } => "led",
+            Command::SetImuRange { .. } => "imu_range",
+            Command::SetSampleRate(_) => "sample_rate",
+        }
+    }
+
+    /// The bytes written to the command's characteristic.
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            Command::Vibrate { pattern, duration } => {
+                let millis = duration.as_millis().min(u16::MAX as u128) as u16;
+                let mut data = vec![*pattern as u8];
+                data.extend_from_slice(&millis.to_be_bytes());
+                data
+            }
+            Command::SetLed { r, g, b } => vec![*r, *g, *b],
+            Command::SetImuRange { accel_g, gyro_dps } => {
+                let mut data = vec![*accel_g];
+                data.extend_from_slice(&gyro_dps.to_be_bytes());
+                data
+            }
+            Command::SetSampleRate(hz) => hz.to_be_bytes().to_vec(),
+        }
+    }
+
+    /// Whether the write should wait for the glove to acknowledge it. Settings have to land,
+    /// haptics and the LED are better late than slow.
+    pub fn needs_response(&self) -> bool {
+        match self {
+            Command::Vibrate { .. } | Command::SetLed { .. } => false,
+            Command::SetImuRange { .. } | Command::SetSampleRate(_) => true,
+        }
+    }
+}
+
+/// Where commands for a glove go. Added to the glove entity by transports that can write to it.
+#[derive(Component)]
+pub struct CommandQueue(pub mpsc::UnboundedSender<Command>);
+
+fn dispatch_commands(
+    mut commands: EventReader<GloveCommand>,
+    mut failed: EventWriter<GloveCommandFailed>,
+    gloves: Query<(&Handedness, Option<&CommandQueue>)>,
+) {
+    for GloveCommand { hand, command } in commands.read() {
+        let queue = gloves
+            .iter()
+            .find(|(glove_hand, _)| *glove_hand == hand)
+            .and_then(|(_, queue)| queue);
+
+        let error = match queue {
+            Some(CommandQueue(sender)) => match sender.send(command.clone()) {
+                Ok(()) => continue,
+                Err(_) => "the glove's transport has stopped",
+            },
+            None => "the glove's source can't take commands",
+        };
+        failed.send(GloveCommandFailed {
+            hand: *hand,
+            command: command.clone(),
+            error: String::from(error),
+        });
+    }
+}
+
+fn log_failed_commands(mut failed: EventReader<GloveCommandFailed>) {
+    for GloveCommandFailed { hand, command, error } in failed.read() {
+        warn!("{:?} glove {} command failed: {}", hand, command.name(), error);
+    }
+}
+
+/// Sends the commands from `on_connect` in glove.toml whenever a glove that takes commands
+/// starts streaming, e.g. to set its sample rate.
+fn send_on_connect(
+    gloves: Query<(&Handedness, &GloveConnection), (Changed<GloveConnection>, With<CommandQueue>)>,
+    config: Res<GloveConfig>,
+    mut commands: EventWriter<GloveCommand>,
+) {
+    for (hand, connection) in gloves.iter() {
+        if *connection != GloveConnection::Streaming {
+            continue;
+        }
+        for command in config.on_connect.iter() {
+            commands.send(GloveCommand {
+                hand: *hand,
+                command: command.clone(),
+            });
+        }
+    }
+}
@@ -0,0 +1,536 @@
+// Settings for finding and talking to the glove, loaded from a TOML file with CLI overrides
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use bevy::ecs::system::Resource;
+use bevy::log::error;
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::adaptive::AdaptiveConfig;
+use crate::command::Command;
+use crate::curl::CurlConfig;
+use crate::filters::FiltersConfig;
+use crate::recording::{self, ExportFormat};
+use crate::ruka::Handedness;
+
+#[derive(Parser, Debug)]
+#[command(about = "Bevy app for receiving bluetooth data from a custom controller glove")]
+pub struct Cli {
+    /// Path to the glove config file
+    #[arg(long, default_value = "glove.toml")]
+    pub config: PathBuf,
+    /// Path to the channel schema describing what the glove sends
+    #[arg(long)]
+    pub schema: Option<PathBuf>,
+
+    /// Which glove --source, --name, --address, --port, --listen and --forward-to apply to
+    #[arg(long, value_enum, default_value_t = Handedness::Right)]
+    pub hand: Handedness,
+    /// Where that glove's data comes from
+    #[arg(long, value_enum)]
+    pub source: Option<GloveSource>,
+    /// Advertised name of the glove to connect to
+    #[arg(long)]
+    pub name: Option<String>,
+    /// MAC address of the glove to connect to, e.g. AA:BB:CC:DD:EE:FF
+    #[arg(long)]
+    pub address: Option<String>,
+    /// Serial port of that glove, for the serial source
+    #[arg(long)]
+    pub port: Option<String>,
+    /// Address that glove listens on, for the udp source
+    #[arg(long)]
+    pub listen: Option<String>,
+    /// Relay that glove's samples to another instance at this address
+    #[arg(long)]
+    pub forward_to: Option<String>,
+    /// Only scan for devices advertising this service
+    #[arg(long)]
+    pub service_uuid: Option<Uuid>,
+    /// Seconds to scan before giving up and retrying
+    #[arg(long)]
+    pub scan_timeout: Option<u64>,
+    /// Index of the Bluetooth adapter to use
+    #[arg(long)]
+    pub adapter: Option<usize>,
+
+    /// Whose calibrations to use, see calibrations.toml. Defaults to whoever was picked last.
+    #[arg(long)]
+    pub profile: Option<String>,
+    /// Play back this recording instead of connecting to the gloves
+    #[arg(long, value_name = "RECORDING")]
+    pub replay: Option<PathBuf>,
+    /// Convert this recording for analysis and exit, instead of starting the app
+    #[arg(long, value_name = "RECORDING")]
+    pub export: Option<PathBuf>,
+    /// What --export converts to. The result is written next to the recording.
+    #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+    pub export_format: ExportFormat,
+}
+
+/// Where a glove's data comes from.
+#[derive(Deserialize, clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum GloveSource {
+    #[default]
+    Ble,
+    Hid,
+    /// A simulated glove on the HID path, for running without hardware
+    FakeHid,
+    Serial,
+    /// Packets relayed over the network by another instance, see net.rs
+    Udp,
+    /// A simulated glove on the BLE path, set up in [mock_ble]. See mock_ble.rs
+    MockBle,
+    /// A recorded session, see replay.rs. Set for every recorded hand by `replay`.
+    Replay,
+}
+
+/// One physical glove and how to recognise it.
+#[derive(Deserialize, Debug, Clone)]
+pub struct GloveDevice {
+    pub hand: Handedness,
+    #[serde(default)]
+    pub source: GloveSource,
+    /// Advertised local name to match. Ignored if None.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// MAC address to match. Ignored if None.
+    #[serde(default)]
+    pub address: Option<String>,
+    /// USB serial number, to tell gloves apart when more than one is plugged in.
+    #[serde(default)]
+    pub serial: Option<String>,
+    /// Serial port for the serial source. Falls back to the one in [serial].
+    #[serde(default)]
+    pub port: Option<String>,
+    /// Address to listen on for the udp source. Falls back to the one in [net].
+    #[serde(default)]
+    pub listen: Option<String>,
+    /// Relay every sample from this glove to another instance listening at this address.
+    #[serde(default)]
+    pub forward_to: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct NetConfig {
+    pub listen: String,
+}
+
+impl Default for NetConfig {
+    fn default() -> Self {
+        Self {
+            listen: String::from("0.0.0.0:9750"),
+        }
+    }
+}
+
+/// How the firmware frames data on the serial port. See serial.rs for the formats.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SerialProtocol {
+    #[default]
+    Binary,
+    Line,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SerialConfig {
+    pub port: String,
+    pub baud_rate: u32,
+    pub protocol: SerialProtocol,
+}
+
+impl Default for SerialConfig {
+    fn default() -> Self {
+        Self {
+            port: String::from("/dev/ttyACM0"),
+            baud_rate: 115200,
+            protocol: SerialProtocol::Binary,
+        }
+    }
+}
+
+/// USB HID settings shared by every glove plugged in over USB.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct HidConfig {
+    pub vendor_id: u16,
+    pub product_id: u16,
+}
+
+/// How the simulated BLE glove behaves, for gloves with source = "mock-ble".
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct MockBleConfig {
+    /// Advertised local name
+    pub name: String,
+    /// Seconds after startup before the glove starts advertising
+    pub advertise_after: f32,
+    /// How many connection attempts fail before one goes through
+    pub connect_failures: u32,
+    /// Seconds of streaming before the glove drops the connection, 0 to never drop it
+    pub disconnect_after: f32,
+    /// Notifications per second, taking turns between packets
+    pub rate: f32,
+    /// Payloads to send instead of the built in motion, played in order and looped
+    pub script: Vec<MockFrame>,
+}
+
+/// One scripted notification: the values of every channel of a schema packet.
+#[derive(Deserialize, Debug, Clone)]
+pub struct MockFrame {
+    pub packet: String,
+    pub values: Vec<f32>,
+}
+
+impl Default for MockBleConfig {
+    fn default() -> Self {
+        Self {
+            name: String::from("Ruka"),
+            advertise_after: 1.0,
+            connect_failures: 0,
+            disconnect_after: 0.0,
+            rate: 200.0,
+            script: Vec::new(),
+        }
+    }
+}
+
+impl Default for HidConfig {
+    fn default() -> Self {
+        Self {
+            // Arduino Nano 33 BLE
+            vendor_id: 0x2341,
+            product_id: 0x805A,
+        }
+    }
+}
+
+#[derive(Resource, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct GloveConfig {
+    /// The gloves to connect to, at most one per hand.
+    pub gloves: Vec<GloveDevice>,
+    /// Channel schema describing what the glove sends, see schema.rs
+    pub schema: PathBuf,
+    /// Where the device picker remembers the BLE device last chosen for each hand
+    pub remembered_devices: PathBuf,
+    /// Read from `remembered_devices` on startup
+    #[serde(skip)]
+    pub remembered: RememberedDevices,
+    /// Service UUID used as the scan filter. Scans for everything if None.
+    pub service_uuid: Option<Uuid>,
+
+    /// Seconds to scan before giving up and retrying
+    pub scan_timeout: u64,
+    pub adapter_index: usize,
+
+    pub hid: HidConfig,
+    pub serial: SerialConfig,
+    pub net: NetConfig,
+    pub mock_ble: MockBleConfig,
+
+    /// Commands sent to every glove that takes them whenever it connects
+    pub on_connect: Vec<Command>,
+    /// Battery percentage at which to warn about a glove running out
+    pub low_battery: u8,
+    /// How many samples can wait for the next frame, across all gloves. When it's full the
+    /// oldest samples are dropped to make room.
+    pub sample_queue: usize,
+    /// How finger readings become curl, see curl.rs
+    pub curl: CurlConfig,
+    /// Finger ranges that follow drift, see adaptive.rs
+    pub adaptive: AdaptiveConfig,
+    /// Filter chains by channel name or role, see filters.rs
+    pub filters: FiltersConfig,
+    /// Where the recorder saves sessions
+    pub recordings: PathBuf,
+    /// Recording to play back instead of connecting to the gloves
+    pub replay: Option<PathBuf>,
+    /// Where calibrations are saved, per user and glove
+    pub calibrations: PathBuf,
+    /// Whose calibrations to use. Defaults to whoever was picked last in the profile switcher.
+    pub profile: Option<String>,
+
+    /// Recording to export instead of running, from the command line only
+    #[serde(skip)]
+    pub export: Option<PathBuf>,
+    #[serde(skip)]
+    pub export_format: ExportFormat,
+}
+
+impl Default for GloveConfig {
+    fn default() -> Self {
+        Self {
+            gloves: vec![GloveDevice {
+                name: Some(String::from("Ruka")),
+                ..GloveDevice::new(Handedness::Right)
+            }],
+            schema: PathBuf::from("schema.toml"),
+            remembered_devices: PathBuf::from("devices.toml"),
+            remembered: RememberedDevices::default(),
+            service_uuid: None,
+            scan_timeout: 20,
+            adapter_index: 0,
+            hid: HidConfig::default(),
+            serial: SerialConfig::default(),
+            net: NetConfig::default(),
+            mock_ble: MockBleConfig::default(),
+            on_connect: Vec::new(),
+            low_battery: 20,
+            sample_queue: 4096,
+            curl: CurlConfig::default(),
+            adaptive: AdaptiveConfig::default(),
+            filters: FiltersConfig::default(),
+            recordings: PathBuf::from("recordings"),
+            replay: None,
+            calibrations: PathBuf::from("calibrations.toml"),
+            profile: None,
+            export: None,
+            export_format: ExportFormat::default(),
+        }
+    }
+}
+
+impl GloveConfig {
+    /// Parses the command line, reads the config file it points to and applies any overrides.
+    /// A missing config file just means the defaults are used.
+    pub fn load() -> Self {
+        let cli = Cli::parse();
+
+        let mut config = match std::fs::read_to_string(&cli.config) {
+            Ok(contents) => match toml::from_str::<GloveConfig>(&contents) {
+                Ok(config) => config,
+                Err(err) => {
+                    eprintln!("Error parsing {:?}, using defaults: {}", cli.config, err);
+                    GloveConfig::default()
+                }
+            },
+            Err(_) => {
+                println!("No config found at {:?}, using defaults", cli.config);
+                GloveConfig::default()
+            }
+        };
+
+        config.curl.tidy();
+        config.remembered = RememberedDevices::load(&config.remembered_devices);
+        config.apply_remembered();
+        config.apply_cli(cli);
+        config.apply_replay();
+        config
+    }
+
+    /// A device picked in the picker last time takes the place of the name and address
+    /// in the config file. The command line still wins.
+    fn apply_remembered(&mut self) {
+        for glove in self.gloves.iter_mut() {
+            if glove.source != GloveSource::Ble {
+                continue;
+            }
+            if let Some(address) = self.remembered.get(glove.hand) {
+                glove.address = Some(address.clone());
+                glove.name = None;
+            }
+        }
+    }
+
+    /// When replaying, every hand in the recording plays from it, and gloves that aren't in
+    /// the recording are left out.
+    fn apply_replay(&mut self) {
+        let Some(path) = self.replay.as_ref() else {
+            return;
+        };
+        let hands = match recording::read_all(path) {
+            Ok((_, records)) => {
+                let mut hands: Vec<Handedness> = Vec::new();
+                for record in records.iter() {
+                    if !hands.contains(&record.hand()) {
+                        hands.push(record.hand());
+                    }
+                }
+                hands
+            }
+            Err(err) => {
+                eprintln!("Error reading {:?} for replay, using the configured gloves: {}", path, err);
+                self.replay = None;
+                return;
+            }
+        };
+
+        let mut gloves = Vec::new();
+        for hand in hands {
+            let mut glove = match self.gloves.iter().find(|glove| glove.hand == hand) {
+                Some(glove) => glove.clone(),
+                None => GloveDevice::new(hand),
+            };
+            glove.source = GloveSource::Replay;
+            gloves.push(glove);
+        }
+        self.gloves = gloves;
+    }
+
+    fn apply_cli(&mut self, cli: Cli) {
+        let overrides_glove = cli.source.is_some()
+            || cli.name.is_some()
+            || cli.address.is_some()
+            || cli.port.is_some()
+            || cli.listen.is_some()
+            || cli.forward_to.is_some();
+        if overrides_glove {
+            let glove = match self.gloves.iter().position(|glove| glove.hand == cli.hand) {
+                Some(i) => &mut self.gloves[i],
+                None => {
+                    self.gloves.push(GloveDevice::new(cli.hand));
+                    self.gloves.last_mut().unwrap()
+                }
+            };
+            if let Some(source) = cli.source {
+                glove.source = source;
+            }
+            if cli.name.is_some() {
+                glove.name = cli.name;
+            }
+            if cli.address.is_some() {
+                glove.address = cli.address;
+            }
+            if cli.port.is_some() {
+                glove.port = cli.port;
+            }
+            if cli.listen.is_some() {
+                glove.listen = cli.listen;
+            }
+            if cli.forward_to.is_some() {
+                glove.forward_to = cli.forward_to;
+            }
+        }
+        if cli.replay.is_some() {
+            self.replay = cli.replay;
+        }
+        if cli.profile.is_some() {
+            self.profile = cli.profile;
+        }
+        self.export = cli.export;
+        self.export_format = cli.export_format;
+        if let Some(schema) = cli.schema {
+            self.schema = schema;
+        }
+        if cli.service_uuid.is_some() {
+            self.service_uuid = cli.service_uuid;
+        }
+        if let Some(scan_timeout) = cli.scan_timeout {
+            self.scan_timeout = scan_timeout;
+        }
+        if let Some(adapter) = cli.adapter {
+            self.adapter_index = adapter;
+        }
+    }
+
+    pub fn scan_timeout(&self) -> Duration {
+        Duration::from_secs(self.scan_timeout)
+    }
+}
+
+/// The BLE device last chosen in the device picker for each hand, by address.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RememberedDevices {
+    #[serde(default)]
+    pub left: Option<String>,
+    #[serde(default)]
+    pub right: Option<String>,
+    /// Why the file couldn't be read. It's loaded before logging is up, so the device picker
+    /// reports this once the app starts.
+    #[serde(skip)]
+    pub load_error: Option<String>,
+}
+
+impl RememberedDevices {
+    /// A missing file just means nothing has been picked yet.
+    pub fn load(path: &Path) -> Self {
+        let Ok(contents) = std::fs::read_to_string(path) else {
+            return RememberedDevices::default();
+        };
+        match toml::from_str::<RememberedDevices>(&contents) {
+            Ok(remembered) => remembered,
+            Err(err) => RememberedDevices {
+                load_error: Some(format!("Error parsing {:?}, forgetting picked devices: {}", path, err)),
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn save(&self, path: &Path) {
+        let contents = match toml::to_string(self) {
+            Ok(contents) => contents,
+            Err(err) => {
+                error!("Error writing picked devices: {}", err);
+                return;
+            }
+        };
+        let contents = format!("# Written by the device picker: the BLE device last connected to for each hand\n\n{}", contents);
+        if let Err(err) = std::fs::write(path, contents) {
+            error!("Error saving picked devices to {:?}: {}", path, err);
+        }
+    }
+
+    pub fn get(&self, hand: Handedness) -> Option<&String> {
+        match hand {
+            Handedness::Left => self.left.as_ref(),
+            Handedness::Right => self.right.as_ref(),
+        }
+    }
+
+    pub fn set(&mut self, hand: Handedness, address: Option<String>) {
+        match hand {
+            Handedness::Left => self.left = address,
+            Handedness::Right => self.right = address,
+        }
+    }
+}
+
+impl GloveDevice {
+    /// A BLE glove on the given hand that matches anything.
+    pub fn new(hand: Handedness) -> Self {
+        Self {
+            hand,
+            source: GloveSource::Ble,
+            name: None,
+            address: None,
+            serial: None,
+            port: None,
+            listen: None,
+            forward_to: None,
+        }
+    }
+
+    /// Human readable description of what we're looking for, for logging.
+    pub fn target(&self) -> String {
+        let target = match (&self.name, &self.address) {
+            (Some(name), Some(address)) => format!("{} ({})", name, address),
+            (Some(name), None) => name.clone(),
+            (None, Some(address)) => address.clone(),
+            (None, None) => String::from("(any name)"),
+        };
+        format!("{:?} glove {}", self.hand, target)
+    }
+
+    /// Whether an advertising peripheral is the one this config is looking for.
+    pub fn matches(&self, local_name: Option<&str>, address: &str) -> bool {
+        if let Some(name) = &self.name {
+            if local_name != Some(name.as_str()) {
+                return false;
+            }
+        }
+        if let Some(target_address) = &self.address {
+            if !target_address.eq_ignore_ascii_case(address) {
+                return false;
+            }
+        }
+        true
+    }
+}
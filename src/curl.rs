@@ -0,0 +1,266 @@
+// How far each finger is curled, from 0 (straight) to 1 (flexed), set up in [curl] in glove.toml.
+//
+// The raw reading is placed within the finger's (flexed, straight) limits, learned or
+// calibrated, then optionally inverted, trimmed by a deadzone at either end so a relaxed hand
+// reads 0 and a fist reads 1, and shaped by a response curve.
+
+use serde::Deserialize;
+
+/// Shapes curl after the deadzones. Input and output are both 0 to 1.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CurlCurve {
+    #[default]
+    Linear,
+    /// curl^gamma. Above 1 is less sensitive near straight, below 1 more.
+    Gamma(f32),
+    /// Smooth curve through [curl, output] points, which never overshoots between them.
+    Spline(Vec<[f32; 2]>),
+}
+
+impl CurlCurve {
+    pub fn apply(&self, curl: f32) -> f32 {
+        match self {
+            CurlCurve::Linear => curl,
+            CurlCurve::Gamma(gamma) => curl.powf(gamma.max(0.01)),
+            CurlCurve::Spline(points) => monotone_spline(points, curl),
+        }
+    }
+
+    /// Puts spline points in order and drops any that share a curl with the one before.
+    fn tidy(&mut self) {
+        if let CurlCurve::Spline(points) = self {
+            points.sort_by(|a, b| a[0].total_cmp(&b[0]));
+            points.dedup_by(|b, a| a[0] == b[0]);
+        }
+    }
+}
+
+/// Monotone cubic (Fritsch-Carlson) interpolation through `points`, sorted by x.
+/// Flat beyond the first and last point.
+fn monotone_spline(points: &[[f32; 2]], x: f32) -> f32 {
+    let (Some(first), Some(last)) = (points.first(), points.last()) else {
+        return x;
+    };
+    if points.len() == 1 || x <= first[0] {
+        return first[1];
+    }
+    if x >= last[0] {
+        return last[1];
+    }
+
+    let secants: Vec<f32> = points
+        .windows(2)
+        .map(|pair| (pair[1][1] - pair[0][1]) / (pair[1][0] - pair[0][0]))
+        .collect();
+    let mut tangents = Vec::with_capacity(points.len());
+    tangents.push(secants[0]);
+    for pair in secants.windows(2) {
+        tangents.push(match pair[0] * pair[1] <= 0.0 {
+            true => 0.0,
+            false => (pair[0] + pair[1]) / 2.0,
+        });
+    }
+    tangents.push(secants[secants.len() - 1]);
+    for (k, secant) in secants.iter().enumerate() {
+        if *secant == 0.0 {
+            tangents[k] = 0.0;
+            tangents[k + 1] = 0.0;
+            continue;
+        }
+        let (a, b) = (tangents[k] / secant, tangents[k + 1] / secant);
+        let s = a * a + b * b;
+        if s > 9.0 {
+            let t = 3.0 / s.sqrt();
+            tangents[k] = t * a * secant;
+            tangents[k + 1] = t * b * secant;
+        }
+    }
+
+    let i = points.windows(2).position(|pair| x < pair[1][0]).unwrap_or(points.len() - 2);
+    let ([x0, y0], [x1, y1]) = (points[i], points[i + 1]);
+    let h = x1 - x0;
+    let t = (x - x0) / h;
+    let (t2, t3) = (t * t, t * t * t);
+    (2.0 * t3 - 3.0 * t2 + 1.0) * y0
+        + (t3 - 2.0 * t2 + t) * h * tangents[i]
+        + (-2.0 * t3 + 3.0 * t2) * y1
+        + (t3 - t2) * h * tangents[i + 1]
+}
+
+/// How one finger's raw reading becomes curl.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct CurlMapping {
+    /// For sensors that read lower the straighter the finger is, while the limits are still
+    /// being learned. Calibrated limits already know which way round they are.
+    pub invert: bool,
+    /// Curl below this reads as 0
+    pub deadzone_open: f32,
+    /// Curl above 1 minus this reads as 1
+    pub deadzone_closed: f32,
+    pub curve: CurlCurve,
+}
+
+impl Default for CurlMapping {
+    fn default() -> Self {
+        Self {
+            invert: false,
+            deadzone_open: 0.05,
+            deadzone_closed: 0.05,
+            curve: CurlCurve::Linear,
+        }
+    }
+}
+
+impl CurlMapping {
+    /// Curl of a raw reading, given the finger's (flexed, straight) limits.
+    pub fn apply(&self, raw: f32, (flexed, straight): (u16, u16)) -> f32 {
+        let range = straight as f32 - flexed as f32;
+        if range == 0.0 {
+            return 0.0;
+        }
+        let mut curl = ((straight as f32 - raw) / range).clamp(0.0, 1.0);
+        if self.invert {
+            curl = 1.0 - curl;
+        }
+
+        let live = 1.0 - self.deadzone_open - self.deadzone_closed;
+        curl = match live > 0.0 {
+            true => ((curl - self.deadzone_open) / live).clamp(0.0, 1.0),
+            false if curl >= 0.5 => 1.0,
+            false => 0.0,
+        };
+        self.curve.apply(curl)
+    }
+}
+
+/// [curl] in glove.toml: the mapping for every finger, and optionally a different one for
+/// some of them.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct CurlConfig {
+    #[serde(flatten)]
+    pub mapping: CurlMapping,
+    /// Replaces `mapping` for the finger at the same index, thumb first. Fingers past the end
+    /// use `mapping`.
+    pub fingers: Vec<CurlMapping>,
+}
+
+impl CurlConfig {
+    /// The mapping of each of `count` fingers.
+    pub fn mappings(&self, count: usize) -> Vec<CurlMapping> {
+        (0..count)
+            .map(|finger| self.fingers.get(finger).unwrap_or(&self.mapping).clone())
+            .collect()
+    }
+
+    pub fn tidy(&mut self) {
+        self.mapping.curve.tidy();
+        for finger in self.fingers.iter_mut() {
+            finger.curve.tidy();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Flexed reads 1000, straight 11000, so every 100 counts is 1% of curl.
+    const LIMITS: (u16, u16) = (1000, 11000);
+
+    fn linear(deadzone_open: f32, deadzone_closed: f32) -> CurlMapping {
+        CurlMapping { deadzone_open, deadzone_closed, ..Default::default() }
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn places_readings_within_the_limits() {
+        let mapping = linear(0.0, 0.0);
+        assert_eq!(mapping.apply(11000.0, LIMITS), 0.0);
+        assert_eq!(mapping.apply(1000.0, LIMITS), 1.0);
+        assert!(close(mapping.apply(6000.0, LIMITS), 0.5));
+        // Past the limits is as far as it goes
+        assert_eq!(mapping.apply(20000.0, LIMITS), 0.0);
+        assert_eq!(mapping.apply(0.0, LIMITS), 1.0);
+        // Limits not learned yet
+        assert_eq!(mapping.apply(6000.0, (0, 0)), 0.0);
+        assert_eq!(mapping.apply(6000.0, (5000, 5000)), 0.0);
+    }
+
+    #[test]
+    fn inverts() {
+        let mapping = CurlMapping { invert: true, ..linear(0.0, 0.0) };
+        assert_eq!(mapping.apply(11000.0, LIMITS), 1.0);
+        assert!(close(mapping.apply(3000.0, LIMITS), 0.2));
+    }
+
+    #[test]
+    fn deadzones_clamp_both_ends() {
+        let mapping = linear(0.1, 0.2);
+        // Up to 10% curl reads straight, from 80% on reads flexed
+        assert_eq!(mapping.apply(11000.0, LIMITS), 0.0);
+        assert!(close(mapping.apply(10000.0, LIMITS), 0.0));
+        assert!(mapping.apply(9900.0, LIMITS) > 0.0);
+        assert!(close(mapping.apply(3000.0, LIMITS), 1.0));
+        assert_eq!(mapping.apply(2000.0, LIMITS), 1.0);
+        // and what's left is stretched over the whole range
+        assert!(close(mapping.apply(5500.0, LIMITS), (0.55 - 0.1) / 0.7));
+    }
+
+    #[test]
+    fn deadzones_that_cover_everything_make_a_switch() {
+        let mapping = linear(0.6, 0.6);
+        assert_eq!(mapping.apply(6500.0, LIMITS), 0.0);
+        assert_eq!(mapping.apply(6000.0, LIMITS), 1.0);
+        assert_eq!(mapping.apply(1000.0, LIMITS), 1.0);
+    }
+
+    #[test]
+    fn gamma_keeps_the_ends() {
+        let curve = CurlCurve::Gamma(2.0);
+        assert_eq!(curve.apply(0.0), 0.0);
+        assert_eq!(curve.apply(1.0), 1.0);
+        assert!(close(curve.apply(0.5), 0.25));
+        // No gamma at all would flatten everything to 1
+        assert!(CurlCurve::Gamma(0.0).apply(0.5) < 1.0);
+    }
+
+    #[test]
+    fn spline_goes_through_its_points_without_overshooting() {
+        let mut curve = CurlCurve::Spline(vec![[1.0, 1.0], [0.0, 0.0], [0.5, 0.9], [0.5, 0.1], [0.6, 0.9]]);
+        curve.tidy();
+        assert_eq!(curve, CurlCurve::Spline(vec![[0.0, 0.0], [0.5, 0.9], [0.6, 0.9], [1.0, 1.0]]));
+
+        for [x, y] in [[0.0, 0.0], [0.5, 0.9], [0.6, 0.9], [1.0, 1.0]] {
+            assert!(close(curve.apply(x), y), "{} -> {}", x, curve.apply(x));
+        }
+        // Flat stays flat, and it never goes down
+        assert!(close(curve.apply(0.55), 0.9));
+        let samples: Vec<f32> = (0..=100).map(|i| curve.apply(i as f32 / 100.0)).collect();
+        assert!(samples.windows(2).all(|pair| pair[1] >= pair[0] - 1e-6));
+        assert!(samples.iter().all(|y| (0.0..=1.0).contains(y)));
+    }
+
+    #[test]
+    fn short_splines() {
+        assert_eq!(CurlCurve::Spline(Vec::new()).apply(0.3), 0.3);
+        assert_eq!(CurlCurve::Spline(vec![[0.5, 0.7]]).apply(0.3), 0.7);
+        assert_eq!(CurlCurve::Spline(vec![[0.2, 0.1], [0.8, 0.9]]).apply(0.0), 0.1);
+        assert_eq!(CurlCurve::Spline(vec![[0.2, 0.1], [0.8, 0.9]]).apply(1.0), 0.9);
+    }
+
+    #[test]
+    fn fingers_override_the_mapping() {
+        let config = CurlConfig {
+            mapping: linear(0.1, 0.1),
+            fingers: vec![linear(0.0, 0.0)],
+        };
+        let mappings = config.mappings(3);
+        assert_eq!(mappings, [linear(0.0, 0.0), linear(0.1, 0.1), linear(0.1, 0.1)]);
+    }
+}
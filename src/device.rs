@@ -0,0 +1,152 @@
+// What the glove tells us about itself: battery level and the Device Information strings.
+// Filled in by transports that can read them (BLE for now) and shown in the connection overlay.
+
+use std::time::Duration;
+
+use bevy::app::{App, Plugin, Update};
+use bevy::ecs::component::Component;
+use bevy::ecs::event::{Event, EventReader, EventWriter};
+use bevy::ecs::query::Changed;
+use bevy::ecs::system::{Query, Res};
+use bevy::log::warn;
+use btleplug::api::bleuuid::uuid_from_u16;
+use uuid::Uuid;
+
+use crate::command::{Command, GloveCommand, VibrationPattern};
+use crate::config::GloveConfig;
+use crate::ruka::Handedness;
+
+/// Battery Service: Battery Level, one byte percentage
+pub const BATTERY_LEVEL_UUID: Uuid = uuid_from_u16(0x2A19);
+/// Device Information Service strings
+pub const SERIAL_NUMBER_UUID: Uuid = uuid_from_u16(0x2A25);
+pub const FIRMWARE_REVISION_UUID: Uuid = uuid_from_u16(0x2A26);
+pub const HARDWARE_REVISION_UUID: Uuid = uuid_from_u16(0x2A27);
+
+pub const DEVICE_INFO_UUIDS: [Uuid; 4] = [
+    BATTERY_LEVEL_UUID,
+    SERIAL_NUMBER_UUID,
+    FIRMWARE_REVISION_UUID,
+    HARDWARE_REVISION_UUID,
+];
+
+pub struct DevicePlugin;
+
+impl Plugin for DevicePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_event::<LowBattery>()
+            .add_systems(Update, warn_low_battery)
+            .add_systems(Update, alert_low_battery)
+        ;
+    }
+}
+
+#[derive(Component, Default, Debug, Clone)]
+pub struct DeviceInfo {
+    /// Bluetooth address of the device the glove's transport is connected to, for BLE gloves
+    pub address: Option<String>,
+    /// Percent, 0 to 100
+    pub battery_level: Option<u8>,
+    pub firmware_revision: Option<String>,
+    pub hardware_revision: Option<String>,
+    pub serial_number: Option<String>,
+    /// Whether LowBattery has been sent for the current discharge, so it only fires once.
+    low_battery_warned: bool,
+}
+
+impl DeviceInfo {
+    /// Forgets everything about the previous device once a transport is connected to a new one,
+    /// which may be a different glove, e.g. after switching in the device picker.
+    pub fn new_session(&mut self, address: Option<String>) {
+        *self = DeviceInfo {
+            address,
+            ..DeviceInfo::default()
+        };
+    }
+
+    /// Stores a value read from one of the characteristics above. Returns false for anything else.
+    pub fn update(&mut self, uuid: Uuid, value: &[u8]) -> bool {
+        let text = || Some(String::from_utf8_lossy(value).trim_end_matches('\0').trim().to_string());
+        match uuid {
+            BATTERY_LEVEL_UUID => self.battery_level = value.first().copied(),
+            FIRMWARE_REVISION_UUID => self.firmware_revision = text(),
+            HARDWARE_REVISION_UUID => self.hardware_revision = text(),
+            SERIAL_NUMBER_UUID => self.serial_number = text(),
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn is_low(&self, threshold: u8) -> bool {
+        self.battery_level.is_some_and(|level| level <= threshold)
+    }
+
+    /// Short summary for the overlay, e.g. "87% fw 1.2.0 hw C sn 0042". Empty if nothing is known.
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(level) = self.battery_level {
+            parts.push(format!("{}%", level));
+        }
+        if let Some(firmware) = &self.firmware_revision {
+            parts.push(format!("fw {}", firmware));
+        }
+        if let Some(hardware) = &self.hardware_revision {
+            parts.push(format!("hw {}", hardware));
+        }
+        if let Some(serial) = &self.serial_number {
+            parts.push(format!("sn {}", serial));
+        }
+        parts.join(" ")
+    }
+}
+
+/// Sent once when a glove's battery drops to `low_battery` percent in glove.toml.
+#[derive(Event, Debug, Clone)]
+pub struct LowBattery {
+    pub hand: Handedness,
+    pub level: u8,
+}
+
+fn warn_low_battery(
+    mut gloves: Query<(&Handedness, &mut DeviceInfo), Changed<DeviceInfo>>,
+    config: Res<GloveConfig>,
+    mut warnings: EventWriter<LowBattery>,
+) {
+    for (hand, mut info) in gloves.iter_mut() {
+        let Some(level) = info.battery_level else {
+            continue;
+        };
+
+        if !info.is_low(config.low_battery) {
+            // Charged back up, warn again next time
+            if info.low_battery_warned {
+                info.low_battery_warned = false;
+            }
+            continue;
+        }
+        if info.low_battery_warned {
+            continue;
+        }
+
+        info.low_battery_warned = true;
+        warnings.send(LowBattery { hand: *hand, level });
+    }
+}
+
+/// Lets the wearer know too, since the overlay is easy to miss mid-session.
+fn alert_low_battery(
+    mut warnings: EventReader<LowBattery>,
+    mut commands: EventWriter<GloveCommand>,
+) {
+    for LowBattery { hand, level } in warnings.read() {
+        warn!("{:?} glove battery low: {}%", hand, level);
+        commands.send(GloveCommand {
+            hand: *hand,
+            command: Command::Vibrate {
+                pattern: VibrationPattern::DoublePulse,
+                duration: Duration::from_millis(400),
+            },
+        });
+    }
+}
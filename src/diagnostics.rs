@@ -0,0 +1,231 @@
+// Per-glove link statistics, registered as Bevy diagnostics under glove/<hand>/...
+//
+// Transports report RSSI, decode failures and sequence gaps through their GloveSink.
+// Sample rates and jitter come from when samples reach the host, per schema packet, and
+// dropped samples from gaps in GloveSample::seq after the sample queue.
+// Everything is measured once a second and shown in the R debug overlay.
+
+use std::time::{Duration, Instant};
+
+use bevy::app::{App, Plugin, Update};
+use bevy::diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, DiagnosticsStore, RegisterDiagnostic};
+use bevy::ecs::component::Component;
+use bevy::ecs::system::{Query, Res};
+use bevy::text::Text;
+
+use crate::config::GloveConfig;
+use crate::ruka::{ActiveHands, Handedness};
+use crate::schema::GloveSchema;
+use crate::transport::GloveSample;
+
+/// How long samples are counted before the rates and jitter are measured.
+const MEASURE_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct LinkDiagnosticsPlugin;
+
+impl Plugin for LinkDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        // One set of diagnostics per configured glove, so the config and schema have to be in already
+        let config = app.world.resource::<GloveConfig>();
+        let schema = app.world.resource::<GloveSchema>();
+        let diagnostics: Vec<(DiagnosticPath, &'static str)> = config
+            .gloves
+            .iter()
+            .flat_map(|device| LinkDiagnostics::new(device.hand, schema).diagnostics())
+            .collect();
+
+        for (path, suffix) in diagnostics {
+            app.register_diagnostic(Diagnostic::new(path).with_suffix(suffix));
+        }
+        app
+            .add_systems(Update, measure_links)
+            .add_systems(Update, update_link_debug)
+        ;
+    }
+}
+
+/// What a transport has noticed about its link since the last batch of samples.
+#[derive(Default, Debug, Clone)]
+pub struct LinkReport {
+    pub rssi: Option<i16>,
+    pub decode_failures: u64,
+    pub sequence_gaps: u64,
+}
+
+impl LinkReport {
+    pub fn is_empty(&self) -> bool {
+        self.rssi.is_none() && self.decode_failures == 0 && self.sequence_gaps == 0
+    }
+}
+
+/// Link statistics of one glove, collected between measurements.
+#[derive(Component)]
+pub struct LinkDiagnostics {
+    /// e.g. "glove/right"
+    prefix: String,
+    rssi: Option<i16>,
+    /// Totals since the app started
+    decode_failures: u64,
+    sequence_gaps: u64,
+    dropped_samples: u64,
+    last_seq: Option<u64>,
+    packets: Vec<PacketArrivals>,
+    window_start: Instant,
+}
+
+/// When samples of one schema packet arrived during the current window.
+struct PacketArrivals {
+    id: u8,
+    name: String,
+    count: u32,
+    /// Host and device time of the last sample
+    last: Option<(Instant, Option<Duration>)>,
+    /// Milliseconds between consecutive samples. For packets with a timestamp channel, minus the
+    /// time between them on the glove's clock, so only what happened on the way counts.
+    intervals: Vec<f64>,
+}
+
+impl PacketArrivals {
+    /// Standard deviation of the time between samples.
+    fn jitter_ms(&self) -> Option<f64> {
+        if self.intervals.len() < 2 {
+            return None;
+        }
+        let n = self.intervals.len() as f64;
+        let mean = self.intervals.iter().sum::<f64>() / n;
+        let variance = self.intervals.iter().map(|interval| (interval - mean).powi(2)).sum::<f64>() / n;
+        Some(variance.sqrt())
+    }
+}
+
+fn link_path(prefix: &str, name: &str) -> DiagnosticPath {
+    DiagnosticPath::new(format!("{}/{}", prefix, name))
+}
+
+impl LinkDiagnostics {
+    pub fn new(hand: Handedness, schema: &GloveSchema) -> Self {
+        Self {
+            prefix: format!("glove/{:?}", hand).to_lowercase(),
+            rssi: None,
+            decode_failures: 0,
+            sequence_gaps: 0,
+            dropped_samples: 0,
+            last_seq: None,
+            packets: schema
+                .packets
+                .iter()
+                .map(|packet| PacketArrivals {
+                    id: packet.id,
+                    name: packet.name.clone(),
+                    count: 0,
+                    last: None,
+                    intervals: Vec::new(),
+                })
+                .collect(),
+            window_start: Instant::now(),
+        }
+    }
+
+    /// Every diagnostic of this glove and its suffix, in the order they are shown.
+    pub fn diagnostics(&self) -> Vec<(DiagnosticPath, &'static str)> {
+        let mut diagnostics = vec![(link_path(&self.prefix, "rssi"), " dBm")];
+        for packet in self.packets.iter() {
+            diagnostics.push((link_path(&self.prefix, &format!("{}/samples_per_sec", packet.name)), " Hz"));
+            diagnostics.push((link_path(&self.prefix, &format!("{}/jitter", packet.name)), " ms"));
+        }
+        diagnostics.push((link_path(&self.prefix, "decode_failures"), ""));
+        diagnostics.push((link_path(&self.prefix, "sequence_gaps"), ""));
+        diagnostics.push((link_path(&self.prefix, "dropped_samples"), ""));
+        diagnostics
+    }
+
+    /// Counts a sample that made it out of the sample queue.
+    pub fn arrived(&mut self, sample: &GloveSample) {
+        if let Some(last_seq) = self.last_seq {
+            self.dropped_samples += sample.seq.saturating_sub(last_seq + 1);
+        }
+        self.last_seq = Some(sample.seq);
+
+        let Some(arrivals) = self.packets.iter_mut().find(|arrivals| arrivals.id == sample.data.packet) else {
+            return;
+        };
+        arrivals.count += 1;
+        if let Some((last_host, last_device)) = arrivals.last {
+            let mut interval = sample.t_host.saturating_duration_since(last_host).as_secs_f64() * 1000.0;
+            if let (Some(t_device), Some(last_device)) = (sample.t_device, last_device) {
+                interval -= t_device.saturating_sub(last_device).as_secs_f64() * 1000.0;
+            }
+            arrivals.intervals.push(interval);
+        }
+        arrivals.last = Some((sample.t_host, sample.t_device));
+    }
+
+    pub fn apply(&mut self, report: LinkReport) {
+        if report.rssi.is_some() {
+            self.rssi = report.rssi;
+        }
+        self.decode_failures += report.decode_failures;
+        self.sequence_gaps += report.sequence_gaps;
+    }
+
+    /// Turns the window that just ended into measurements and starts a new one.
+    fn measure(&mut self, diagnostics: &mut Diagnostics) {
+        let seconds = self.window_start.elapsed().as_secs_f64();
+        self.window_start = Instant::now();
+
+        if let Some(rssi) = self.rssi {
+            diagnostics.add_measurement(&link_path(&self.prefix, "rssi"), || rssi as f64);
+        }
+        for packet in self.packets.iter_mut() {
+            let rate = packet.count as f64 / seconds;
+            diagnostics.add_measurement(&link_path(&self.prefix, &format!("{}/samples_per_sec", packet.name)), || rate);
+            if let Some(jitter) = packet.jitter_ms() {
+                diagnostics.add_measurement(&link_path(&self.prefix, &format!("{}/jitter", packet.name)), || jitter);
+            }
+            packet.count = 0;
+            packet.intervals.clear();
+        }
+        diagnostics.add_measurement(&link_path(&self.prefix, "decode_failures"), || self.decode_failures as f64);
+        diagnostics.add_measurement(&link_path(&self.prefix, "sequence_gaps"), || self.sequence_gaps as f64);
+        diagnostics.add_measurement(&link_path(&self.prefix, "dropped_samples"), || self.dropped_samples as f64);
+    }
+}
+
+fn measure_links(mut gloves: Query<&mut LinkDiagnostics>, mut diagnostics: Diagnostics) {
+    for mut link in gloves.iter_mut() {
+        if link.window_start.elapsed() >= MEASURE_INTERVAL {
+            link.measure(&mut diagnostics);
+        }
+    }
+}
+
+/// A line of the debug overlay below the channel values, showing the diagnostic at this index
+/// of `LinkDiagnostics::diagnostics`.
+#[derive(Component)]
+pub struct LinkDebugLabel(pub usize);
+
+fn update_link_debug(
+    gloves: Query<(&Handedness, &LinkDiagnostics)>,
+    hands: Res<ActiveHands>,
+    store: Res<DiagnosticsStore>,
+    mut labels: Query<(&mut Text, &LinkDebugLabel)>,
+) {
+    if labels.is_empty() {
+        return;
+    }
+    let Some((_, link)) = gloves.iter().find(|(hand, _)| **hand == hands.debug) else {
+        return;
+    };
+
+    let diagnostics = link.diagnostics();
+    for (mut lbl, LinkDebugLabel(i)) in labels.iter_mut() {
+        let Some((path, suffix)) = diagnostics.get(*i) else {
+            continue;
+        };
+        let name = path.as_str().trim_start_matches(&link.prefix).trim_start_matches('/');
+        lbl.sections[0].value = match store.get(path).and_then(Diagnostic::value) {
+            Some(value) => format!("{}: {:.1}{}", name, value, suffix),
+            None => format!("{}: -", name),
+        };
+    }
+}
@@ -0,0 +1,67 @@
+// Things that can go wrong while talking to a glove. Transports report them through their
+// GloveSink instead of panicking, keep retrying, and the app hears about them as events.
+
+use std::fmt;
+use std::time::Duration;
+
+use bevy::ecs::event::{Event, EventReader};
+use bevy::log::error;
+
+use crate::ruka::Handedness;
+
+#[derive(Debug)]
+pub enum GloveError {
+    /// The platform Bluetooth stack could not be reached
+    Manager(btleplug::Error),
+    /// There is no Bluetooth adapter at `adapter_index` in glove.toml
+    NoAdapter { index: usize },
+    /// The adapter stopped answering
+    Adapter(btleplug::Error),
+    Scan(btleplug::Error),
+    NotFound { target: String, timeout: Duration },
+    Connect(btleplug::Error),
+    DiscoverServices(btleplug::Error),
+    Notifications(btleplug::Error),
+}
+
+impl fmt::Display for GloveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GloveError::Manager(err) => write!(f, "can't reach the Bluetooth stack: {}", err),
+            GloveError::NoAdapter { index } => write!(f, "no Bluetooth adapter found at index {}", index),
+            GloveError::Adapter(err) => write!(f, "Bluetooth adapter error: {}", err),
+            GloveError::Scan(err) => write!(f, "can't scan for devices: {}", err),
+            GloveError::NotFound { target, timeout } => write!(f, "{} was not found within {:?}", target, timeout),
+            GloveError::Connect(err) => write!(f, "error connecting: {}", err),
+            GloveError::DiscoverServices(err) => write!(f, "error discovering services: {}", err),
+            GloveError::Notifications(err) => write!(f, "error getting notification stream: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for GloveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GloveError::Manager(err)
+            | GloveError::Adapter(err)
+            | GloveError::Scan(err)
+            | GloveError::Connect(err)
+            | GloveError::DiscoverServices(err)
+            | GloveError::Notifications(err) => Some(err),
+            GloveError::NoAdapter { .. } | GloveError::NotFound { .. } => None,
+        }
+    }
+}
+
+/// A glove's transport ran into an error. It keeps retrying on its own.
+#[derive(Event, Debug)]
+pub struct GloveErrorEvent {
+    pub hand: Handedness,
+    pub error: GloveError,
+}
+
+pub fn log_glove_errors(mut errors: EventReader<GloveErrorEvent>) {
+    for GloveErrorEvent { hand, error } in errors.read() {
+        error!("{:?} glove: {}", hand, error);
+    }
+}
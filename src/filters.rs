@@ -0,0 +1,352 @@
+// Per-channel filter chains, set up in [filters] in glove.toml and run on every sample as it
+// comes off the sample queue, so they see the glove's sample rate rather than the frame rate.
+//
+// A chain is picked by channel name first, then by role ("flex", "accel", "gyro"), and its
+// filters run in order. Gestures, curl and the camera all see the filtered values, the overlay
+// shows both.
+
+use std::collections::{HashMap, VecDeque};
+use std::f32::consts::PI;
+use std::time::{Duration, Instant};
+
+use bevy::log::warn;
+use serde::Deserialize;
+
+use crate::schema::{ChannelRole, GloveSchema};
+
+/// Seconds between samples assumed until there are two to go by.
+const DEFAULT_DT: f32 = 0.01;
+/// Samples closer together than this arrived in the same batch, rather than being taken that
+/// close together, so the time between them isn't trusted.
+const MIN_DT: f32 = 0.0005;
+/// Longer gaps than this are treated as the link dropping out, not a slow sample.
+const MAX_DT: f32 = 0.5;
+
+/// Smooths one channel, one sample at a time.
+pub trait SignalFilter: Send + Sync {
+    /// Takes the latest reading and the seconds since the one before, returns the filtered value.
+    fn apply(&mut self, value: f32, dt: f32) -> f32;
+}
+
+/// One filter in a chain, as written in glove.toml, e.g. { type = "median", n = 5 }.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum FilterConfig {
+    /// Adaptive low-pass: smooth when still, quick to follow when moving.
+    /// See https://gery.casiez.net/1euro/
+    OneEuro {
+        /// Cutoff in Hz when the signal is still. Lower means smoother.
+        #[serde(default = "default_min_cutoff")]
+        min_cutoff: f32,
+        /// How fast the cutoff rises with speed. Higher means less lag.
+        #[serde(default)]
+        beta: f32,
+        /// Cutoff in Hz for the speed estimate
+        #[serde(default = "default_d_cutoff")]
+        d_cutoff: f32,
+    },
+    /// Exponential low-pass with a cutoff in Hz.
+    LowPass { cutoff: f32 },
+    /// Median of the last n readings, which throws away spikes.
+    Median { n: usize },
+    /// Kalman filter for a value that stays put apart from process noise.
+    Kalman {
+        /// How much the real value is expected to wander, per second
+        process_noise: f32,
+        /// How noisy the sensor is
+        measurement_noise: f32,
+    },
+}
+
+fn default_min_cutoff() -> f32 {
+    1.0
+}
+
+fn default_d_cutoff() -> f32 {
+    1.0
+}
+
+impl FilterConfig {
+    pub fn build(&self) -> Box<dyn SignalFilter> {
+        match *self {
+            FilterConfig::OneEuro { min_cutoff, beta, d_cutoff } => Box::new(OneEuroFilter {
+                min_cutoff,
+                beta,
+                d_cutoff,
+                last: None,
+                speed: 0.0,
+            }),
+            FilterConfig::LowPass { cutoff } => Box::new(LowPassFilter { cutoff, last: None }),
+            FilterConfig::Median { n } => Box::new(MedianFilter {
+                n: n.max(1),
+                window: VecDeque::with_capacity(n.max(1)),
+            }),
+            FilterConfig::Kalman { process_noise, measurement_noise } => Box::new(KalmanFilter {
+                process_noise,
+                measurement_noise,
+                estimate: None,
+                error: 1.0,
+            }),
+        }
+    }
+}
+
+/// How much of a new reading an exponential low-pass with this cutoff takes in.
+fn smoothing(cutoff: f32, dt: f32) -> f32 {
+    let tau = 1.0 / (2.0 * PI * cutoff.max(f32::EPSILON));
+    1.0 / (1.0 + tau / dt)
+}
+
+struct LowPassFilter {
+    cutoff: f32,
+    last: Option<f32>,
+}
+
+impl SignalFilter for LowPassFilter {
+    fn apply(&mut self, value: f32, dt: f32) -> f32 {
+        let filtered = match self.last {
+            Some(last) => last + smoothing(self.cutoff, dt) * (value - last),
+            None => value,
+        };
+        self.last = Some(filtered);
+        filtered
+    }
+}
+
+struct OneEuroFilter {
+    min_cutoff: f32,
+    beta: f32,
+    d_cutoff: f32,
+    last: Option<f32>,
+    /// Smoothed rate of change
+    speed: f32,
+}
+
+impl SignalFilter for OneEuroFilter {
+    fn apply(&mut self, value: f32, dt: f32) -> f32 {
+        let Some(last) = self.last else {
+            self.last = Some(value);
+            return value;
+        };
+        let speed = (value - last) / dt;
+        self.speed += smoothing(self.d_cutoff, dt) * (speed - self.speed);
+        let cutoff = self.min_cutoff + self.beta * self.speed.abs();
+        let filtered = last + smoothing(cutoff, dt) * (value - last);
+        self.last = Some(filtered);
+        filtered
+    }
+}
+
+struct MedianFilter {
+    n: usize,
+    window: VecDeque<f32>,
+}
+
+impl SignalFilter for MedianFilter {
+    fn apply(&mut self, value: f32, _dt: f32) -> f32 {
+        if self.window.len() == self.n {
+            self.window.pop_front();
+        }
+        self.window.push_back(value);
+        let mut sorted: Vec<f32> = self.window.iter().copied().collect();
+        sorted.sort_by(f32::total_cmp);
+        sorted[sorted.len() / 2]
+    }
+}
+
+struct KalmanFilter {
+    process_noise: f32,
+    measurement_noise: f32,
+    estimate: Option<f32>,
+    /// Variance of the estimate
+    error: f32,
+}
+
+impl SignalFilter for KalmanFilter {
+    fn apply(&mut self, value: f32, dt: f32) -> f32 {
+        let Some(estimate) = self.estimate else {
+            self.estimate = Some(value);
+            return value;
+        };
+        self.error += self.process_noise * dt;
+        let gain = self.error / (self.error + self.measurement_noise.max(f32::EPSILON));
+        let estimate = estimate + gain * (value - estimate);
+        self.error *= 1.0 - gain;
+        self.estimate = Some(estimate);
+        estimate
+    }
+}
+
+/// The filters of one channel, run in order.
+pub struct FilterChain(Vec<Box<dyn SignalFilter>>);
+
+impl FilterChain {
+    pub fn apply(&mut self, value: f32, dt: f32) -> f32 {
+        self.0.iter_mut().fold(value, |value, filter| filter.apply(value, dt))
+    }
+}
+
+/// [filters] in glove.toml: chains by channel name or role.
+pub type FiltersConfig = HashMap<String, Vec<FilterConfig>>;
+
+/// A chain for every schema channel that has one configured, in schema order.
+pub fn build_chains(schema: &GloveSchema, config: &FiltersConfig) -> Vec<Option<FilterChain>> {
+    for key in config.keys() {
+        let known = schema.channels().any(|channel| {
+            channel.name == key.as_str() || channel.role.is_some_and(|role| role_name(role) == key.as_str())
+        });
+        if !known {
+            warn!("[filters] has {}, which is not a channel name or role in the schema", key);
+        }
+    }
+
+    schema
+        .channels()
+        .map(|channel| {
+            if channel.role == Some(ChannelRole::Timestamp) {
+                return None;
+            }
+            let filters = config
+                .get(channel.name)
+                .or_else(|| channel.role.and_then(|role| config.get(role_name(role))))?;
+            Some(FilterChain(filters.iter().map(FilterConfig::build).collect()))
+        })
+        .collect()
+}
+
+fn role_name(role: ChannelRole) -> &'static str {
+    match role {
+        ChannelRole::Flex => "flex",
+        ChannelRole::Accel => "accel",
+        ChannelRole::Gyro => "gyro",
+        ChannelRole::Timestamp => "timestamp",
+    }
+}
+
+/// Time between samples of one packet, from the glove's clock if the packet has one.
+#[derive(Default)]
+pub struct SampleClock {
+    last: Option<(Instant, Option<Duration>)>,
+    dt: Option<f32>,
+}
+
+impl SampleClock {
+    /// Seconds since the previous sample.
+    pub fn tick(&mut self, t_host: Instant, t_device: Option<Duration>) -> f32 {
+        if let Some((last_host, last_device)) = self.last {
+            let measured = match (last_device, t_device) {
+                (Some(last), Some(now)) => now.checked_sub(last).map(|dt| dt.as_secs_f32()),
+                _ => Some(t_host.saturating_duration_since(last_host).as_secs_f32()),
+            };
+            if let Some(measured) = measured.filter(|dt| *dt >= MIN_DT) {
+                self.dt = Some(measured.min(MAX_DT));
+            }
+        }
+        self.last = Some((t_host, t_device));
+        self.dt.unwrap_or(DEFAULT_DT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(config: FilterConfig, values: &[f32], dt: f32) -> Vec<f32> {
+        let mut filter = config.build();
+        values.iter().map(|value| filter.apply(*value, dt)).collect()
+    }
+
+    #[test]
+    fn first_reading_passes_through() {
+        let configs = [
+            FilterConfig::OneEuro { min_cutoff: 1.0, beta: 0.0, d_cutoff: 1.0 },
+            FilterConfig::LowPass { cutoff: 1.0 },
+            FilterConfig::Median { n: 5 },
+            FilterConfig::Kalman { process_noise: 1.0, measurement_noise: 1.0 },
+        ];
+        for config in configs {
+            assert_eq!(run(config.clone(), &[42.0], 0.01), [42.0], "{:?}", config);
+        }
+    }
+
+    #[test]
+    fn low_pass_follows_a_step_at_its_cutoff() {
+        let step = run(FilterConfig::LowPass { cutoff: 1.0 }, &[0.0, 1.0, 1.0], 0.01);
+        let alpha = smoothing(1.0, 0.01);
+        assert!((step[1] - alpha).abs() < 1e-6);
+        assert!(step[1] < step[2] && step[2] < 1.0);
+        // A longer gap lets more of the step through
+        let slow = run(FilterConfig::LowPass { cutoff: 1.0 }, &[0.0, 1.0], 0.1);
+        assert!(slow[1] > step[1]);
+    }
+
+    #[test]
+    fn one_euro_catches_up_faster_with_beta() {
+        let ramp: Vec<f32> = (0..50).map(|i| i as f32).collect();
+        let still = run(FilterConfig::OneEuro { min_cutoff: 1.0, beta: 0.0, d_cutoff: 1.0 }, &ramp, 0.01);
+        let quick = run(FilterConfig::OneEuro { min_cutoff: 1.0, beta: 1.0, d_cutoff: 1.0 }, &ramp, 0.01);
+        assert!(still[49] < quick[49] && quick[49] <= 49.0, "{} {}", still[49], quick[49]);
+        // Holding still it settles where it is
+        let held = run(FilterConfig::OneEuro { min_cutoff: 1.0, beta: 1.0, d_cutoff: 1.0 }, &[5.0; 10], 0.01);
+        assert!(held.iter().all(|value| *value == 5.0));
+    }
+
+    #[test]
+    fn median_drops_spikes() {
+        let filtered = run(FilterConfig::Median { n: 3 }, &[1.0, 1.0, 100.0, 1.0, 2.0], 0.01);
+        assert_eq!(filtered, [1.0, 1.0, 1.0, 1.0, 2.0]);
+        // Nothing to take the median of is the same as one reading
+        assert_eq!(run(FilterConfig::Median { n: 0 }, &[1.0, 100.0, 2.0], 0.01), [1.0, 100.0, 2.0]);
+    }
+
+    #[test]
+    fn kalman_weighs_noise() {
+        let trusting = run(FilterConfig::Kalman { process_noise: 1.0, measurement_noise: 0.0 }, &[0.0, 10.0], 0.01);
+        assert!((trusting[1] - 10.0).abs() < 1e-3, "{}", trusting[1]);
+        let doubtful = run(FilterConfig::Kalman { process_noise: 1.0, measurement_noise: 100.0 }, &[0.0, 10.0], 0.01);
+        assert!(doubtful[1] > 0.0 && doubtful[1] < 1.0, "{}", doubtful[1]);
+    }
+
+    #[test]
+    fn chains_by_name_then_role() {
+        let config: FiltersConfig = toml::from_str(
+            r#"
+            flex = [{ type = "median", n = 3 }]
+            index = [{ type = "low-pass", cutoff = 5.0 }, { type = "median", n = 3 }]
+            "#,
+        )
+        .unwrap();
+        let schema = GloveSchema::default();
+        let chains = build_chains(&schema, &config);
+        let lengths: Vec<Option<usize>> = chains.iter().map(|chain| chain.as_ref().map(|chain| chain.0.len())).collect();
+        // thumb, index, middle, ring, pinky, then the IMU
+        assert_eq!(lengths[..5], [Some(1), Some(2), Some(1), Some(1), Some(1)]);
+        assert!(lengths[5..].iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn clock_ignores_batched_samples() {
+        let start = Instant::now();
+        let mut clock = SampleClock::default();
+        assert_eq!(clock.tick(start, None), DEFAULT_DT);
+        // Pulled off the queue together, so no better than the default
+        assert_eq!(clock.tick(start, None), DEFAULT_DT);
+        assert!((clock.tick(start + Duration::from_millis(20), None) - 0.02).abs() < 1e-6);
+        // Below MIN_DT keeps the last real interval
+        let t = start + Duration::from_millis(20) + Duration::from_micros(100);
+        assert!((clock.tick(t, None) - 0.02).abs() < 1e-6);
+    }
+
+    #[test]
+    fn clock_prefers_the_device_and_caps_gaps() {
+        let start = Instant::now();
+        let mut clock = SampleClock::default();
+        clock.tick(start, Some(Duration::from_millis(1000)));
+        // Arrived together, but the glove says they were 5 ms apart
+        assert!((clock.tick(start, Some(Duration::from_millis(1005))) - 0.005).abs() < 1e-6);
+        // A device clock going backwards isn't trusted either
+        assert!((clock.tick(start, Some(Duration::from_millis(900))) - 0.005).abs() < 1e-6);
+        // A dropout counts as MAX_DT, not the whole gap
+        assert_eq!(clock.tick(start, Some(Duration::from_secs(10))), MAX_DT);
+    }
+}
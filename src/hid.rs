@@ -0,0 +1,318 @@
+// USB HID glove source, for when the glove is plugged in for charging.
+// The firmware sends the same payloads as over BLE, as input reports numbered by schema packet id.
+
+use std::time::Duration;
+
+use bevy::app::{App, Plugin, Startup};
+use bevy::ecs::system::{Res, ResMut};
+use bevy::log::{debug, info, warn};
+use hidapi::{HidApi, HidDevice};
+use tokio::sync::mpsc;
+use tokio::{task, time};
+
+use crate::asyncs::TokioTasksRuntime;
+use crate::config::{GloveConfig, GloveSource, HidConfig};
+use crate::ruka::GloveConnection;
+use crate::schema::GloveSchema;
+use crate::transport::{spawn_transport, GloveSample, GloveSink, GloveTransport, SampleQueue};
+
+/// How long to wait before trying to open the device again after it was unplugged or not found.
+const RETRY_INTERVAL: Duration = Duration::from_secs(2);
+/// How long a blocking read waits before checking whether anyone is still listening.
+const READ_TIMEOUT_MS: i32 = 100;
+
+pub struct HidPlugin;
+
+impl Plugin for HidPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Startup, connect_hid)
+        ;
+    }
+}
+
+fn connect_hid(runtime: ResMut<TokioTasksRuntime>, queue: Res<SampleQueue>, config: Res<GloveConfig>, schema: Res<GloveSchema>) {
+    for device in config.gloves.iter() {
+        let backend: Box<dyn HidBackend> = match device.source {
+            GloveSource::Hid => Box::new(HidApiBackend),
+            GloveSource::FakeHid => Box::new(FakeHidBackend { schema: schema.clone() }),
+            _ => continue,
+        };
+        let transport = HidTransport {
+            config: config.hid.clone(),
+            serial: device.serial.clone(),
+            backend,
+        };
+        spawn_transport(&runtime, &queue, device, &schema, transport);
+    }
+}
+
+/// Something that can open the glove's HID interface. Lets the transport run against
+/// a fake device when there's no glove plugged in.
+pub trait HidBackend: Send + 'static {
+    fn name(&self) -> &'static str;
+
+    fn open(&mut self, config: &HidConfig, serial: Option<&str>) -> Result<Box<dyn HidReader>, String>;
+}
+
+/// An opened HID device that input reports can be read from.
+pub trait HidReader: Send + 'static {
+    /// Waits up to `timeout_ms` for the next input report, report ID first.
+    /// Returns the report length, or 0 if nothing arrived in time.
+    fn read_report(&mut self, buf: &mut [u8], timeout_ms: i32) -> Result<usize, String>;
+}
+
+/// Reads one glove over USB HID, reopening the device whenever it is unplugged.
+pub struct HidTransport {
+    config: HidConfig,
+    serial: Option<String>,
+    backend: Box<dyn HidBackend>,
+}
+
+impl GloveTransport for HidTransport {
+    fn name(&self) -> String {
+        String::from(self.backend.name())
+    }
+
+    async fn run(mut self, mut sink: GloveSink) {
+        loop {
+            sink.set_connection(GloveConnection::Connecting).await;
+            match self.backend.open(&self.config, self.serial.as_deref()) {
+                Ok(reader) => {
+                    info!("Opened HID device {:04x}:{:04x}", self.config.vendor_id, self.config.product_id);
+                    sink.set_connection(GloveConnection::Streaming).await;
+                    stream_reports(&mut sink, reader).await;
+                }
+                Err(err) => {
+                    warn!("Error opening HID device {:04x}:{:04x}: {}", self.config.vendor_id, self.config.product_id, err);
+                }
+            }
+
+            sink.set_connection(GloveConnection::Disconnected).await;
+            time::sleep(RETRY_INTERVAL).await;
+        }
+    }
+}
+
+async fn stream_reports(sink: &mut GloveSink, reader: Box<dyn HidReader>) {
+    // HID reads block, so they get their own thread and hand reports over a channel
+    let (report_tx, mut report_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+    let read_task = task::spawn_blocking(move || read_reports(reader, report_tx));
+
+    while let Some(report) = report_rx.recv().await {
+        let mut samples = Vec::new();
+        samples.extend(decode_report(sink, &report));
+        while let Ok(report) = report_rx.try_recv() {
+            samples.extend(decode_report(sink, &report));
+        }
+
+        sink.send(samples).await;
+    }
+
+    let _ = read_task.await;
+}
+
+fn read_reports(mut reader: Box<dyn HidReader>, report_tx: mpsc::UnboundedSender<Vec<u8>>) {
+    let mut buf = [0u8; 64];
+    while !report_tx.is_closed() {
+        match reader.read_report(&mut buf, READ_TIMEOUT_MS) {
+            Ok(0) => continue,
+            Ok(len) => {
+                if report_tx.send(buf[..len].to_vec()).is_err() {
+                    break;
+                }
+            }
+            Err(err) => {
+                warn!("Error reading HID report: {}", err);
+                break;
+            }
+        }
+    }
+}
+
+/// Splits off the report ID and decodes the payload it names.
+fn decode_report(sink: &mut GloveSink, report: &[u8]) -> Option<GloveSample> {
+    let (&report_id, payload) = report.split_first()?;
+    let decoded = sink.schema().decode(report_id, payload);
+    match decoded {
+        Ok(data) => Some(sink.sample(data)),
+        Err(err) => {
+            // Counted in the link diagnostics, so only worth a line when debugging
+            debug!("Dropping HID report {}: {}", report_id, err);
+            sink.decode_failed();
+            None
+        }
+    }
+}
+
+struct HidApiBackend;
+
+impl HidBackend for HidApiBackend {
+    fn name(&self) -> &'static str {
+        "USB HID"
+    }
+
+    fn open(&mut self, config: &HidConfig, serial: Option<&str>) -> Result<Box<dyn HidReader>, String> {
+        let api = HidApi::new().map_err(|err| err.to_string())?;
+        let device = match serial {
+            Some(serial) => api.open_serial(config.vendor_id, config.product_id, serial),
+            None => api.open(config.vendor_id, config.product_id),
+        };
+        Ok(Box::new(device.map_err(|err| err.to_string())?))
+    }
+}
+
+impl HidReader for HidDevice {
+    fn read_report(&mut self, buf: &mut [u8], timeout_ms: i32) -> Result<usize, String> {
+        self.read_timeout(buf, timeout_ms).map_err(|err| err.to_string())
+    }
+}
+
+/// Stands in for a plugged in glove: slowly opens and closes the hand while tilting back and forth.
+/// Sends every packet in the schema, so it keeps working when sensors are added.
+struct FakeHidBackend {
+    schema: GloveSchema,
+}
+
+/// Time between fake reports. Packets take turns, so each one arrives at a fraction of this rate.
+const FAKE_REPORT_INTERVAL: Duration = Duration::from_millis(5);
+
+impl HidBackend for FakeHidBackend {
+    fn name(&self) -> &'static str {
+        "fake USB HID"
+    }
+
+    fn open(&mut self, _config: &HidConfig, _serial: Option<&str>) -> Result<Box<dyn HidReader>, String> {
+        Ok(Box::new(FakeHidDevice {
+            schema: self.schema.clone(),
+            tick: 0,
+        }))
+    }
+}
+
+struct FakeHidDevice {
+    schema: GloveSchema,
+    tick: u32,
+}
+
+impl HidReader for FakeHidDevice {
+    fn read_report(&mut self, buf: &mut [u8], _timeout_ms: i32) -> Result<usize, String> {
+        std::thread::sleep(FAKE_REPORT_INTERVAL);
+        self.tick = self.tick.wrapping_add(1);
+
+        let packet = &self.schema.packets[self.tick as usize % self.schema.packets.len()];
+        let values = packet.synthetic_values(self.tick as f32 * FAKE_REPORT_INTERVAL.as_secs_f32());
+        let payload = packet.encode(&values);
+        if payload.len() >= buf.len() {
+            return Err(format!("packet {} does not fit in a report", packet.name));
+        }
+        buf[0] = packet.id;
+        buf[1..=payload.len()].copy_from_slice(&payload);
+        Ok(1 + payload.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::world::World;
+
+    use super::*;
+    use crate::config::GloveDevice;
+    use crate::ruka::Handedness;
+    use crate::testing::{channels, collect, connection, glove_app, start_transport, update_until, Collected};
+
+    const HAND: Handedness = Handedness::Right;
+
+    /// The fake glove, unplugged after a few reports the first time it is opened.
+    struct UnpluggingBackend {
+        fake: FakeHidBackend,
+        reports: u32,
+        opened: u32,
+    }
+
+    impl HidBackend for UnpluggingBackend {
+        fn name(&self) -> &'static str {
+            "unplugging fake USB HID"
+        }
+
+        fn open(&mut self, config: &HidConfig, serial: Option<&str>) -> Result<Box<dyn HidReader>, String> {
+            let device = self.fake.open(config, serial)?;
+            self.opened += 1;
+            match self.opened {
+                1 => Ok(Box::new(Unplugging { device, left: self.reports })),
+                _ => Ok(device),
+            }
+        }
+    }
+
+    struct Unplugging {
+        device: Box<dyn HidReader>,
+        left: u32,
+    }
+
+    impl HidReader for Unplugging {
+        fn read_report(&mut self, buf: &mut [u8], timeout_ms: i32) -> Result<usize, String> {
+            if self.left == 0 {
+                return Err(String::from("device unplugged"));
+            }
+            self.left -= 1;
+            self.device.read_report(buf, timeout_ms)
+        }
+    }
+
+    #[test]
+    fn streams_fake_glove_and_reopens_after_unplug() {
+        let schema = GloveSchema::default();
+        let device = GloveDevice {
+            source: GloveSource::FakeHid,
+            ..GloveDevice::new(HAND)
+        };
+        let config = GloveConfig {
+            gloves: vec![device.clone()],
+            ..Default::default()
+        };
+        let transport = HidTransport {
+            config: config.hid.clone(),
+            serial: None,
+            backend: Box::new(UnpluggingBackend {
+                fake: FakeHidBackend { schema: schema.clone() },
+                reports: 40,
+                opened: 0,
+            }),
+        };
+
+        let mut app = glove_app(config, schema);
+        collect(&mut app, |sample: &GloveSample| sample.seq);
+        start_transport(&mut app, &device, transport);
+
+        // The fake hand opens and closes around 15000 counts and lies flat, 981 counts being 1g
+        let plausible = |world: &mut World| {
+            let values = channels(world, HAND);
+            values[..5].iter().all(|flex| (13500.0..=16500.0).contains(flex))
+                && values[5..7].iter().all(|accel| accel.abs() < 0.01)
+                && (values[7] - 9.81).abs() < 0.01
+        };
+        let received = |world: &mut World| world.resource::<Collected<u64>>().0.len();
+
+        update_until(&mut app, Duration::from_secs(2), "streaming", |world| {
+            connection(world, HAND) == GloveConnection::Streaming
+        });
+        update_until(&mut app, Duration::from_secs(2), "decoded samples", plausible);
+
+        update_until(&mut app, Duration::from_secs(2), "the unplug", |world| {
+            connection(world, HAND) == GloveConnection::Disconnected
+        });
+        let before = received(&mut app.world);
+        assert!(before <= 40, "got {} samples from 40 reports", before);
+
+        update_until(&mut app, RETRY_INTERVAL * 2, "reopening", |world| {
+            connection(world, HAND) == GloveConnection::Streaming
+        });
+        update_until(&mut app, Duration::from_secs(2), "samples after reopening", |world| {
+            received(world) > before + 10 && plausible(world)
+        });
+
+        let seqs = &app.world.resource::<Collected<u64>>().0;
+        assert!(seqs.windows(2).all(|pair| pair[1] == pair[0] + 1), "samples out of order or missing");
+    }
+}
@@ -1,20 +1,83 @@
+mod adaptive;
 mod asyncs;
 mod ble;
+mod calibration;
+mod command;
+mod config;
+mod curl;
+mod device;
+mod diagnostics;
+mod error;
+mod filters;
+mod hid;
+mod mock_ble;
+mod net;
 mod particles;
+mod picker;
+mod profiles;
+mod protocol;
+mod recorder;
+mod recording;
+mod replay;
 mod ruka;
+mod schema;
+mod serial;
+#[cfg(test)]
+mod testing;
+mod transport;
 
 use bevy::{math::{Affine3A, Mat3A}, prelude::*};
 use bevy_gaussian_splatting::{GaussianCloudSettings, GaussianSplattingBundle, GaussianSplattingPlugin};
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
 use bevy_panorbit_camera::{PanOrbitCamera, PanOrbitCameraPlugin};
+use adaptive::AdaptiveRangePlugin;
 use ble::BLEPlugin;
+use calibration::CalibrationPlugin;
+use command::CommandPlugin;
+use config::GloveConfig;
+use device::DevicePlugin;
+use diagnostics::LinkDiagnosticsPlugin;
+use hid::HidPlugin;
+use net::NetPlugin;
+use picker::DevicePickerPlugin;
+use profiles::ProfilesPlugin;
+use recorder::RecorderPlugin;
+use replay::ReplayPlugin;
 use ruka::RukaPlugin;
+use schema::GloveSchema;
+use serial::SerialPlugin;
+use transport::GloveTransportPlugin;
 
 
 fn main() {
+    let config = GloveConfig::load();
+    if let Some(recording) = &config.export {
+        match recording::export(recording, config.export_format) {
+            Ok(target) => println!("Exported {:?} to {:?}", recording, target),
+            Err(err) => eprintln!("Error exporting {:?}: {}", recording, err),
+        }
+        return;
+    }
+    let schema = GloveSchema::load(&config.schema);
+
     App::new()
+        .insert_resource(config)
+        .insert_resource(schema)
         .add_plugins(DefaultPlugins)
+        .add_plugins(GloveTransportPlugin)
+        .add_plugins(CommandPlugin)
+        .add_plugins(DevicePlugin)
+        .add_plugins(LinkDiagnosticsPlugin)
+        .add_plugins(DevicePickerPlugin)
+        .add_plugins(RecorderPlugin)
+        .add_plugins(CalibrationPlugin)
+        .add_plugins(ProfilesPlugin)
+        .add_plugins(AdaptiveRangePlugin)
         .add_plugins(BLEPlugin)
+        .add_plugins(HidPlugin)
+        .add_plugins(SerialPlugin)
+        .add_plugins(NetPlugin)
+        .add_plugins(ReplayPlugin)
 
         .add_plugins(GaussianSplattingPlugin)
         .add_plugins(PanOrbitCameraPlugin)
@@ -0,0 +1,426 @@
+// A simulated BLE glove for gloves with source = "mock-ble", set up in [mock_ble] in glove.toml.
+//
+// It goes through the same client code as a real glove: it starts advertising after a while,
+// can refuse the first few connections, streams either the built in motion or scripted payloads
+// on the schema's characteristics, and can drop the connection mid-stream.
+
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bevy::log::{info, warn};
+use btleplug::api::{BDAddr, CharPropFlags, Characteristic, PeripheralProperties, ScanFilter, ValueNotification, WriteType};
+use futures::stream;
+use tokio::time;
+use uuid::Uuid;
+
+use crate::ble::{BleBackend, BleCentral, BleEvent, BleEvents, BlePeripheral, BleResult, Notifications};
+use crate::config::MockBleConfig;
+use crate::device::{BATTERY_LEVEL_UUID, FIRMWARE_REVISION_UUID};
+use crate::error::GloveError;
+use crate::schema::GloveSchema;
+
+/// "MOCK" plus a device number
+const MOCK_ADDRESS: [u8; 6] = [0x4D, 0x4F, 0x43, 0x4B, 0x00, 0x01];
+/// How often the mock re-advertises once it has started.
+const ADVERTISING_INTERVAL: Duration = Duration::from_secs(1);
+/// How long connecting takes, successful or not.
+const CONNECT_DELAY: Duration = Duration::from_millis(300);
+/// Seconds per percent of battery lost while connected.
+const BATTERY_DRAIN_SECS: f32 = 30.0;
+
+pub struct MockBleBackend {
+    glove: Arc<MockGlove>,
+}
+
+impl MockBleBackend {
+    pub fn new(config: MockBleConfig, schema: GloveSchema) -> Self {
+        for frame in config.script.iter() {
+            if !schema.packets.iter().any(|packet| packet.name == frame.packet) {
+                warn!("Mock glove script plays packet {}, which is not in the schema", frame.packet);
+            }
+        }
+        Self {
+            glove: Arc::new(MockGlove {
+                config,
+                schema,
+                started: Instant::now(),
+                connect_attempts: AtomicU32::new(0),
+                connected: AtomicBool::new(false),
+                connection: AtomicU32::new(0),
+            }),
+        }
+    }
+}
+
+impl BleBackend for MockBleBackend {
+    type Central = MockCentral;
+
+    fn name(&self) -> &'static str {
+        "mock BLE"
+    }
+
+    async fn open(&self, _adapter_index: usize) -> Result<MockCentral, GloveError> {
+        Ok(MockCentral(self.glove.clone()))
+    }
+}
+
+/// State shared by the mock adapter and the one peripheral it can see.
+struct MockGlove {
+    config: MockBleConfig,
+    schema: GloveSchema,
+    started: Instant,
+    connect_attempts: AtomicU32,
+    connected: AtomicBool,
+    /// Bumped on every connect, so a notification stream from an older connection ends
+    connection: AtomicU32,
+}
+
+impl MockGlove {
+    fn address(&self) -> String {
+        BDAddr::from(MOCK_ADDRESS).to_string()
+    }
+
+    fn advertise_at(&self) -> Instant {
+        self.started + Duration::from_secs_f32(self.config.advertise_after.max(0.0))
+    }
+
+    fn is_advertising(&self) -> bool {
+        Instant::now() >= self.advertise_at()
+    }
+
+    fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    fn battery_level(&self) -> u8 {
+        let drained = self.started.elapsed().as_secs_f32() / BATTERY_DRAIN_SECS;
+        100u8.saturating_sub(drained as u8).max(5)
+    }
+
+    /// The payload of notification number `tick`, if it goes anywhere.
+    fn frame(&self, tick: usize) -> Option<(Uuid, Vec<u8>)> {
+        if !self.config.script.is_empty() {
+            let frame = &self.config.script[tick % self.config.script.len()];
+            let packet = self.schema.packets.iter().find(|packet| packet.name == frame.packet)?;
+            return Some((packet.uuid?, packet.encode(&frame.values)));
+        }
+        let packet = self.schema.packets.get(tick % self.schema.packets.len().max(1))?;
+        let values = packet.synthetic_values(self.started.elapsed().as_secs_f32());
+        Some((packet.uuid?, packet.encode(&values)))
+    }
+
+    fn characteristics(&self) -> Vec<Characteristic> {
+        let characteristic = |uuid: Uuid, properties: CharPropFlags| Characteristic {
+            uuid,
+            service_uuid: Uuid::nil(),
+            properties,
+            descriptors: Default::default(),
+        };
+
+        let mut characteristics: Vec<Characteristic> = self
+            .schema
+            .packets
+            .iter()
+            .filter_map(|packet| packet.uuid)
+            .map(|uuid| characteristic(uuid, CharPropFlags::READ | CharPropFlags::NOTIFY))
+            .collect();
+        characteristics.push(characteristic(BATTERY_LEVEL_UUID, CharPropFlags::READ));
+        characteristics.push(characteristic(FIRMWARE_REVISION_UUID, CharPropFlags::READ));
+
+        let commands = &self.schema.commands;
+        for uuid in [commands.vibrate, commands.led, commands.imu_range, commands.sample_rate].into_iter().flatten() {
+            if characteristics.iter().all(|known| known.uuid != uuid) {
+                characteristics.push(characteristic(uuid, CharPropFlags::WRITE | CharPropFlags::WRITE_WITHOUT_RESPONSE));
+            }
+        }
+        characteristics
+    }
+}
+
+#[derive(Clone)]
+pub struct MockCentral(Arc<MockGlove>);
+
+impl BleCentral for MockCentral {
+    type Id = String;
+    type Peripheral = MockPeripheral;
+
+    async fn events(&self) -> BleResult<BleEvents<String>> {
+        let glove = self.0.clone();
+        let events = stream::unfold(false, move |advertised| {
+            let glove = glove.clone();
+            async move {
+                if advertised {
+                    time::sleep(ADVERTISING_INTERVAL).await;
+                    Some((BleEvent::Updated(glove.address()), true))
+                } else {
+                    time::sleep_until(glove.advertise_at().into()).await;
+                    Some((BleEvent::Discovered(glove.address()), true))
+                }
+            }
+        });
+        Ok(Box::pin(events))
+    }
+
+    async fn start_scan(&self, _filter: ScanFilter) -> BleResult<()> {
+        Ok(())
+    }
+
+    async fn stop_scan(&self) -> BleResult<()> {
+        Ok(())
+    }
+
+    async fn peripherals(&self) -> BleResult<Vec<MockPeripheral>> {
+        match self.0.is_advertising() {
+            true => Ok(vec![MockPeripheral(self.0.clone())]),
+            false => Ok(Vec::new()),
+        }
+    }
+
+    async fn peripheral(&self, id: &String) -> BleResult<MockPeripheral> {
+        if *id != self.0.address() || !self.0.is_advertising() {
+            return Err(btleplug::Error::DeviceNotFound);
+        }
+        Ok(MockPeripheral(self.0.clone()))
+    }
+}
+
+#[derive(Clone)]
+pub struct MockPeripheral(Arc<MockGlove>);
+
+impl MockPeripheral {
+    fn check_connected(&self) -> BleResult<()> {
+        match self.0.is_connected() {
+            true => Ok(()),
+            false => Err(btleplug::Error::NotConnected),
+        }
+    }
+}
+
+impl BlePeripheral for MockPeripheral {
+    type Id = String;
+
+    fn id(&self) -> String {
+        self.0.address()
+    }
+
+    fn address(&self) -> String {
+        self.0.address()
+    }
+
+    async fn properties(&self) -> BleResult<Option<PeripheralProperties>> {
+        if !self.0.is_advertising() {
+            return Ok(None);
+        }
+        // Wander around a bit so the diagnostics have something to show
+        let rssi = -55.0 + 5.0 * (self.0.started.elapsed().as_secs_f32() / 3.0).sin();
+        Ok(Some(PeripheralProperties {
+            address: BDAddr::from(MOCK_ADDRESS),
+            local_name: Some(self.0.config.name.clone()),
+            rssi: Some(rssi as i16),
+            ..Default::default()
+        }))
+    }
+
+    async fn is_connected(&self) -> BleResult<bool> {
+        Ok(self.0.is_connected())
+    }
+
+    async fn connect(&self) -> BleResult<()> {
+        time::sleep(CONNECT_DELAY).await;
+        let attempt = self.0.connect_attempts.fetch_add(1, Ordering::SeqCst) + 1;
+        if attempt <= self.0.config.connect_failures {
+            info!("Mock glove refusing connection attempt {}", attempt);
+            return Err(btleplug::Error::TimedOut(CONNECT_DELAY));
+        }
+        self.0.connection.fetch_add(1, Ordering::SeqCst);
+        self.0.connected.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> BleResult<()> {
+        self.0.connected.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn discover_services(&self) -> BleResult<()> {
+        self.check_connected()
+    }
+
+    fn characteristics(&self) -> Vec<Characteristic> {
+        match self.0.is_connected() {
+            true => self.0.characteristics(),
+            false => Vec::new(),
+        }
+    }
+
+    async fn subscribe(&self, _characteristic: &Characteristic) -> BleResult<()> {
+        self.check_connected()
+    }
+
+    async fn unsubscribe(&self, _characteristic: &Characteristic) -> BleResult<()> {
+        self.check_connected()
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> BleResult<Vec<u8>> {
+        self.check_connected()?;
+        match characteristic.uuid {
+            BATTERY_LEVEL_UUID => Ok(vec![self.0.battery_level()]),
+            FIRMWARE_REVISION_UUID => Ok(b"mock".to_vec()),
+            uuid => {
+                let packet = self
+                    .0
+                    .schema
+                    .by_uuid(uuid)
+                    .ok_or(btleplug::Error::NoSuchCharacteristic)?;
+                Ok(packet.encode(&packet.synthetic_values(self.0.started.elapsed().as_secs_f32())))
+            }
+        }
+    }
+
+    async fn write(&self, characteristic: &Characteristic, data: &[u8], write_type: WriteType) -> BleResult<()> {
+        self.check_connected()?;
+        info!("Mock glove got {:?} on {} ({:?})", data, characteristic.uuid, write_type);
+        Ok(())
+    }
+
+    async fn notifications(&self) -> BleResult<Notifications> {
+        self.check_connected()?;
+        let glove = self.0.clone();
+        let connection = glove.connection.load(Ordering::SeqCst);
+        let connected_at = Instant::now();
+        let mut interval = time::interval(Duration::from_secs_f32(1.0 / glove.config.rate.max(1.0)));
+        interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+
+        let notifications = stream::unfold((interval, 0usize), move |(mut interval, mut tick)| {
+            let glove = glove.clone();
+            async move {
+                loop {
+                    interval.tick().await;
+                    if !glove.is_connected() || glove.connection.load(Ordering::SeqCst) != connection {
+                        return None;
+                    }
+                    let disconnect_after = glove.config.disconnect_after;
+                    if disconnect_after > 0.0 && connected_at.elapsed().as_secs_f32() >= disconnect_after {
+                        info!("Mock glove dropping the connection after {}s", disconnect_after);
+                        glove.connected.store(false, Ordering::SeqCst);
+                        return None;
+                    }
+
+                    tick = tick.wrapping_add(1);
+                    if let Some((uuid, value)) = glove.frame(tick) {
+                        return Some((ValueNotification { uuid, value }, (interval, tick)));
+                    }
+                }
+            }
+        });
+        Ok(Box::pin(notifications))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::app::{App, Update};
+    use bevy::ecs::query::Changed;
+    use bevy::ecs::system::{Query, ResMut};
+    use bevy::ecs::world::World;
+
+    use super::*;
+    use crate::ble::BLEPlugin;
+    use crate::config::{GloveConfig, GloveDevice, GloveSource, MockFrame, RememberedDevices};
+    use crate::error::GloveErrorEvent;
+    use crate::picker::DevicePicker;
+    use crate::ruka::{GloveConnection, Handedness};
+    use crate::testing::{channels, collect, connection, glove_app, update_until, Collected};
+
+    const HAND: Handedness = Handedness::Right;
+    const FLEX: [f32; 5] = [1000.0, 2000.0, 3000.0, 4000.0, 5000.0];
+    const IMU: [f32; 6] = [0.5, -0.25, 9.81, 10.0, 20.0, -30.0];
+
+    /// A headless app with one mock glove that advertises right away and plays `mock`'s script.
+    fn mock_app(mock: MockBleConfig) -> App {
+        let config = GloveConfig {
+            gloves: vec![GloveDevice {
+                source: GloveSource::MockBle,
+                ..GloveDevice::new(HAND)
+            }],
+            mock_ble: MockBleConfig {
+                advertise_after: 0.0,
+                script: vec![
+                    MockFrame { packet: String::from("flex"), values: FLEX.to_vec() },
+                    MockFrame { packet: String::from("imu"), values: IMU.to_vec() },
+                ],
+                ..mock
+            },
+            ..Default::default()
+        };
+        let mut app = glove_app(config, GloveSchema::default());
+        app
+            .insert_resource(DevicePicker::new(RememberedDevices::default()))
+            .insert_resource(Collected::<GloveConnection>(Vec::new()))
+            .add_plugins(BLEPlugin)
+            .add_systems(Update, record_connections)
+        ;
+        collect(&mut app, |event: &GloveErrorEvent| matches!(event.error, GloveError::Connect(_)));
+        app
+    }
+
+    fn record_connections(gloves: Query<&GloveConnection, Changed<GloveConnection>>, mut seen: ResMut<Collected<GloveConnection>>) {
+        seen.0.extend(gloves.iter().copied());
+    }
+
+    /// Whether the glove went through `states` in this order, whatever else happened in between.
+    fn went_through(world: &mut World, states: &[GloveConnection]) -> bool {
+        let mut seen = world.resource::<Collected<GloveConnection>>().0.iter();
+        states.iter().all(|state| seen.any(|seen| seen == state))
+    }
+
+    fn scripted_values(world: &mut World) -> bool {
+        let values = channels(world, HAND);
+        values[..5] == FLEX && values[5..].iter().zip(IMU).all(|(value, expected)| (value - expected).abs() < 1e-4)
+    }
+
+    fn connect_errors(world: &mut World) -> usize {
+        world.resource::<Collected<bool>>().0.iter().filter(|connect| **connect).count()
+    }
+
+    #[test]
+    fn streams_scripted_payloads() {
+        let mut app = mock_app(MockBleConfig::default());
+        update_until(&mut app, Duration::from_secs(3), "the scripted values", scripted_values);
+
+        assert_eq!(connection(&mut app.world, HAND), GloveConnection::Streaming);
+        assert!(went_through(&mut app.world, &[GloveConnection::Scanning, GloveConnection::Connecting, GloveConnection::Streaming]));
+        assert_eq!(connect_errors(&mut app.world), 0);
+    }
+
+    #[test]
+    fn retries_refused_connections() {
+        let mut app = mock_app(MockBleConfig {
+            connect_failures: 2,
+            ..Default::default()
+        });
+        // Two refusals take 1s and 2s of backoff
+        update_until(&mut app, Duration::from_secs(8), "streaming", |world| {
+            connection(world, HAND) == GloveConnection::Streaming
+        });
+
+        assert_eq!(connect_errors(&mut app.world), 2);
+        update_until(&mut app, Duration::from_secs(1), "the scripted values", scripted_values);
+    }
+
+    #[test]
+    fn reconnects_after_dropped_connection() {
+        let mut app = mock_app(MockBleConfig {
+            disconnect_after: 0.5,
+            ..Default::default()
+        });
+        update_until(&mut app, Duration::from_secs(3), "the scripted values", scripted_values);
+        update_until(&mut app, Duration::from_secs(5), "a reconnect", |world| {
+            went_through(world, &[GloveConnection::Streaming, GloveConnection::Disconnected, GloveConnection::Streaming])
+        });
+
+        // Dropping the connection isn't an error, just a reason to reconnect
+        assert_eq!(connect_errors(&mut app.world), 0);
+    }
+}
@@ -0,0 +1,404 @@
+// UDP glove source and relay, for when the BLE dongle is on a different machine than the renderer.
+//
+// Each datagram carries one sample: a 17 byte header followed by the same payload
+// that comes over BLE.
+//
+//   "RK" | version u8 | glove id u8 | kind u8 | sequence u32 | sender timestamp u64 (us)
+//
+// All big-endian. The version is protocol::PROTOCOL_VERSION, the glove id is the hand (0 left, 1 right)
+// and kind is the schema packet id. Both ends need the same schema.
+// The sequence number counts up per glove on the sending side, which is how loss and
+// reordering are spotted here.
+// The timestamp is the glove's own clock when the sample has one and the relay's otherwise.
+// Either way it becomes the sample's device time here, so jitter is measured against the sender.
+//
+// Gloves listening on the same address share one socket, and each datagram goes to the
+// glove its id names. That way one relay can send both hands to the same port.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket as StdUdpSocket};
+use std::time::{Duration, Instant};
+
+use bevy::app::{App, Plugin, Startup};
+use bevy::ecs::system::{Res, ResMut};
+use bevy::log::{debug, info, warn};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::time;
+
+use crate::asyncs::TokioTasksRuntime;
+use crate::config::{GloveConfig, GloveSource};
+use crate::protocol::{check_version, SampleData, PROTOCOL_VERSION};
+use crate::ruka::{GloveConnection, Handedness};
+use crate::schema::GloveSchema;
+use crate::transport::{spawn_transport, GloveSample, GloveSink, GloveTransport, SampleQueue};
+
+const MAGIC: [u8; 2] = *b"RK";
+const HEADER_LEN: usize = 17;
+
+/// How long without a packet before the glove counts as disconnected.
+const LINK_TIMEOUT: Duration = Duration::from_secs(2);
+/// How often packet loss and reordering are logged, at debug level.
+const STATS_INTERVAL: Duration = Duration::from_secs(5);
+const RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+pub struct NetPlugin;
+
+impl Plugin for NetPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Startup, listen_udp)
+        ;
+    }
+}
+
+fn listen_udp(runtime: ResMut<TokioTasksRuntime>, queue: Res<SampleQueue>, config: Res<GloveConfig>, schema: Res<GloveSchema>) {
+    let mut listeners: HashMap<String, UdpListener> = HashMap::new();
+    for device in config.gloves.iter() {
+        if device.source != GloveSource::Udp {
+            continue;
+        }
+        let listen = device.listen.clone().unwrap_or_else(|| config.net.listen.clone());
+        let (datagram_tx, datagram_rx) = mpsc::unbounded_channel();
+        listeners
+            .entry(listen.clone())
+            .or_insert_with(|| UdpListener { listen: listen.clone(), gloves: Vec::new() })
+            .gloves
+            .push((glove_id(device.hand), datagram_tx));
+
+        let transport = UdpTransport {
+            listen,
+            glove_id: glove_id(device.hand),
+            datagrams: datagram_rx,
+        };
+        spawn_transport(&runtime, &queue, device, &schema, transport);
+    }
+
+    for listener in listeners.into_values() {
+        runtime.spawn_background_task(move |ctx| async move {
+            let shutdown = ctx.shutdown_token();
+            tokio::select! {
+                _ = listener.run() => {}
+                _ = shutdown.cancelled() => {}
+            }
+        });
+    }
+}
+
+fn glove_id(hand: Handedness) -> u8 {
+    match hand {
+        Handedness::Left => 0,
+        Handedness::Right => 1,
+    }
+}
+
+/// One sample as it travels over the network.
+pub struct NetPacket {
+    pub glove_id: u8,
+    pub seq: u32,
+    pub timestamp_us: u64,
+    pub data: SampleData,
+}
+
+pub fn encode_packet(schema: &GloveSchema, packet: &NetPacket) -> Option<Vec<u8>> {
+    let payload = schema.by_id(packet.data.packet)?.encode_sample(&packet.data);
+    let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+    bytes.extend_from_slice(&MAGIC);
+    bytes.push(PROTOCOL_VERSION);
+    bytes.push(packet.glove_id);
+    bytes.push(packet.data.packet);
+    bytes.extend_from_slice(&packet.seq.to_be_bytes());
+    bytes.extend_from_slice(&packet.timestamp_us.to_be_bytes());
+    bytes.extend_from_slice(&payload);
+    Some(bytes)
+}
+
+pub fn decode_packet(schema: &GloveSchema, bytes: &[u8]) -> Option<NetPacket> {
+    if bytes.len() < HEADER_LEN || bytes[0..2] != MAGIC {
+        return None;
+    }
+    check_version(bytes[2]).ok()?;
+    let data = schema.decode(bytes[4], &bytes[HEADER_LEN..]).ok()?;
+
+    Some(NetPacket {
+        glove_id: bytes[3],
+        seq: u32::from_be_bytes(bytes[5..9].try_into().ok()?),
+        timestamp_us: u64::from_be_bytes(bytes[9..17].try_into().ok()?),
+        data,
+    })
+}
+
+/// Owns the socket for one listen address and hands every datagram to the glove it is for.
+struct UdpListener {
+    listen: String,
+    gloves: Vec<(u8, mpsc::UnboundedSender<Vec<u8>>)>,
+}
+
+impl UdpListener {
+    async fn run(self) {
+        loop {
+            match UdpSocket::bind(self.listen.as_str()).await {
+                Ok(socket) => {
+                    info!("Listening for glove packets on {}", self.listen);
+                    self.dispatch(&socket).await;
+                }
+                Err(err) => {
+                    warn!("Error binding {}: {}", self.listen, err);
+                }
+            }
+            time::sleep(RETRY_INTERVAL).await;
+        }
+    }
+
+    async fn dispatch(&self, socket: &UdpSocket) {
+        let mut buf = [0u8; 512];
+        loop {
+            let len = match socket.recv_from(&mut buf).await {
+                Ok((len, _)) => len,
+                Err(err) => {
+                    warn!("Error receiving glove packet on {}: {}", self.listen, err);
+                    return;
+                }
+            };
+
+            // Anything too short to name a glove goes to all of them, to be counted as invalid
+            let bytes = &buf[..len];
+            for (glove_id, datagram_tx) in self.gloves.iter() {
+                if bytes.get(3).is_none_or(|id| id == glove_id) {
+                    let _ = datagram_tx.send(bytes.to_vec());
+                }
+            }
+        }
+    }
+}
+
+/// Receives one glove's samples from a relay, as dispatched by the `UdpListener` of its address.
+pub struct UdpTransport {
+    listen: String,
+    glove_id: u8,
+    datagrams: mpsc::UnboundedReceiver<Vec<u8>>,
+}
+
+impl GloveTransport for UdpTransport {
+    fn name(&self) -> String {
+        format!("UDP {}", self.listen)
+    }
+
+    async fn run(mut self, mut sink: GloveSink) {
+        sink.set_connection(GloveConnection::Connecting).await;
+        receive_packets(&mut sink, &mut self.datagrams, self.glove_id).await;
+    }
+}
+
+async fn receive_packets(sink: &mut GloveSink, datagrams: &mut mpsc::UnboundedReceiver<Vec<u8>>, glove_id: u8) {
+    let mut stats = LinkStats::default();
+    let mut stats_timer = time::interval(STATS_INTERVAL);
+    let mut streaming = false;
+
+    loop {
+        let received = tokio::select! {
+            received = time::timeout(LINK_TIMEOUT, datagrams.recv()) => received,
+            _ = stats_timer.tick() => {
+                stats.log(glove_id);
+                continue;
+            }
+        };
+
+        let datagram = match received {
+            Ok(Some(datagram)) => datagram,
+            // The listener is gone, which only happens when the app exits
+            Ok(None) => return,
+            Err(_) => {
+                // Quiet link. Keep waiting for the relay to come back.
+                if streaming {
+                    streaming = false;
+                    sink.set_connection(GloveConnection::Disconnected).await;
+                }
+                continue;
+            }
+        };
+
+        let mut samples = Vec::new();
+        stats.accept(sink, glove_id, &datagram, &mut samples);
+        while let Ok(datagram) = datagrams.try_recv() {
+            stats.accept(sink, glove_id, &datagram, &mut samples);
+        }
+
+        if !streaming && !samples.is_empty() {
+            streaming = true;
+            sink.set_connection(GloveConnection::Streaming).await;
+        }
+        sink.send(samples).await;
+    }
+}
+
+/// Packet counters for one glove's link.
+#[derive(Default)]
+struct LinkStats {
+    next_seq: Option<u32>,
+    received: u64,
+    lost: u64,
+    reordered: u64,
+    invalid: u64,
+}
+
+impl LinkStats {
+    /// Checks a datagram against the sequence so far and adds its sample if it's new.
+    /// Late packets are counted but dropped, since newer data has already been applied.
+    /// Invalid packets and gaps are also reported to the sink's link diagnostics.
+    fn accept(&mut self, sink: &mut GloveSink, glove_id: u8, bytes: &[u8], samples: &mut Vec<GloveSample>) {
+        let Some(packet) = decode_packet(sink.schema(), bytes).filter(|packet| packet.glove_id == glove_id) else {
+            self.invalid += 1;
+            sink.decode_failed();
+            return;
+        };
+
+        self.received += 1;
+        if let Some(expected) = self.next_seq {
+            let ahead = packet.seq.wrapping_sub(expected);
+            if ahead > u32::MAX / 2 {
+                // Already counted as lost when the gap opened up
+                self.reordered += 1;
+                self.lost = self.lost.saturating_sub(1);
+                return;
+            }
+            if ahead > 0 {
+                sink.sequence_gap();
+            }
+            self.lost += ahead as u64;
+        }
+        self.next_seq = Some(packet.seq.wrapping_add(1));
+
+        let mut sample = sink.sample(packet.data);
+        // The payload's own timestamp channel wins, the header only has it to the microsecond
+        sample.t_device.get_or_insert(Duration::from_micros(packet.timestamp_us));
+        samples.push(sample);
+    }
+
+    fn log(&self, glove_id: u8) {
+        if self.received == 0 && self.invalid == 0 {
+            return;
+        }
+        // The same counts are in the link diagnostics, so these are only for debugging
+        debug!(
+            "UDP glove {}: received {}, lost {}, reordered {}, invalid {}",
+            glove_id, self.received, self.lost, self.reordered, self.invalid
+        );
+    }
+}
+
+/// Relays a glove's samples to another instance running the udp source.
+pub struct UdpForwarder {
+    schema: GloveSchema,
+    socket: StdUdpSocket,
+    target: SocketAddr,
+    glove_id: u8,
+    seq: u32,
+    start: Instant,
+}
+
+impl UdpForwarder {
+    pub fn new(target: &str, hand: Handedness, schema: GloveSchema) -> io::Result<Self> {
+        let target = target
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "address did not resolve"))?;
+        let socket = StdUdpSocket::bind(("0.0.0.0", 0))?;
+
+        Ok(Self {
+            schema,
+            socket,
+            target,
+            glove_id: glove_id(hand),
+            seq: 0,
+            start: Instant::now(),
+        })
+    }
+
+    pub fn forward(&mut self, sample: &GloveSample) {
+        let timestamp = sample
+            .t_device
+            .unwrap_or_else(|| sample.t_host.checked_duration_since(self.start).unwrap_or_default());
+        let packet = NetPacket {
+            glove_id: self.glove_id,
+            seq: self.seq,
+            timestamp_us: timestamp.as_micros() as u64,
+            data: sample.data.clone(),
+        };
+        self.seq = self.seq.wrapping_add(1);
+
+        let Some(bytes) = encode_packet(&self.schema, &packet) else {
+            return;
+        };
+        if let Err(err) = self.socket.send_to(&bytes, self.target) {
+            warn!("Error relaying glove packet to {}: {}", self.target, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GloveDevice;
+    use crate::protocol::KIND_FLEX;
+    use crate::testing::{channels, collect, connection, glove_app, update_until, Collected};
+
+    #[test]
+    fn gloves_share_listen_address() {
+        // Somewhere nothing else is listening
+        let listen = StdUdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let gloves = [Handedness::Left, Handedness::Right].map(|hand| GloveDevice {
+            source: GloveSource::Udp,
+            listen: Some(listen.clone()),
+            ..GloveDevice::new(hand)
+        });
+        let config = GloveConfig {
+            gloves: gloves.to_vec(),
+            ..Default::default()
+        };
+        let schema = GloveSchema::default();
+        let mut app = glove_app(config, schema.clone());
+        app.add_plugins(NetPlugin);
+        collect(&mut app, |sample: &GloveSample| sample.t_device);
+
+        let relay = StdUdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut seq = 0;
+        let mut frames = 0;
+        let expected = |hand| [1000.0, 2000.0, 3000.0, 4000.0, 5000.0].map(|value| value + glove_id(hand) as f32);
+        update_until(&mut app, Duration::from_secs(2), "samples for both gloves", |world| {
+            // Until the listener is up, datagrams just get lost
+            if frames % 20 == 0 {
+                for hand in [Handedness::Left, Handedness::Right] {
+                    let packet = NetPacket {
+                        glove_id: glove_id(hand),
+                        seq,
+                        timestamp_us: seq as u64 * 10_000,
+                        data: SampleData {
+                            packet: KIND_FLEX,
+                            values: expected(hand).to_vec(),
+                            timestamp: None,
+                            raw: Vec::new(),
+                        },
+                    };
+                    relay.send_to(&encode_packet(&schema, &packet).unwrap(), listen.as_str()).unwrap();
+                }
+                seq += 1;
+            }
+            frames += 1;
+            [Handedness::Left, Handedness::Right]
+                .into_iter()
+                .all(|hand| channels(world, hand)[..5] == expected(hand))
+        });
+
+        for hand in [Handedness::Left, Handedness::Right] {
+            assert_eq!(connection(&mut app.world, hand), GloveConnection::Streaming);
+        }
+        // Without a timestamp channel, the relay's clock is the device time
+        let collected = &app.world.resource::<Collected<Option<Duration>>>().0;
+        assert!(!collected.is_empty());
+        assert!(collected
+            .iter()
+            .all(|t_device| t_device.is_some_and(|t| t.as_micros() % 10_000 == 0)));
+    }
+}
@@ -0,0 +1,179 @@
+// Device picker: an egui panel listing every BLE device in range, toggled with B.
+//
+// While the panel is open the BLE plugin scans and fills in ScanResults. Connect points a
+// glove's BLE task at the device and remembers it for the next launch, Disconnect lets go of
+// it and forgets it.
+
+use bevy::app::{App, Plugin, Update};
+use bevy::ecs::system::{Query, Res, ResMut, Resource};
+use bevy::input::{keyboard::KeyCode, ButtonInput};
+use bevy::log::warn;
+use bevy_inspector_egui::bevy_egui::{egui, EguiContexts, EguiPlugin};
+use btleplug::api::bleuuid::BleUuid;
+use tokio::sync::watch;
+use uuid::Uuid;
+
+use crate::ble::{BleControl, BleControlQueue};
+use crate::config::{GloveConfig, RememberedDevices};
+use crate::device::DeviceInfo;
+use crate::ruka::{GloveConnection, Handedness};
+
+pub struct DevicePickerPlugin;
+
+impl Plugin for DevicePickerPlugin {
+    fn build(&self, app: &mut App) {
+        let mut remembered = app.world.resource::<GloveConfig>().remembered.clone();
+        if let Some(err) = remembered.load_error.take() {
+            warn!("{}", err);
+        }
+        if !app.is_plugin_added::<EguiPlugin>() {
+            app.add_plugins(EguiPlugin);
+        }
+        app
+            .insert_resource(DevicePicker::new(remembered))
+            .init_resource::<ScanResults>()
+            .add_systems(Update, toggle_device_picker)
+            .add_systems(Update, show_device_picker)
+        ;
+    }
+}
+
+#[derive(Resource)]
+pub struct DevicePicker {
+    open: bool,
+    /// Tells the scanner whether anyone is looking
+    scanning: watch::Sender<bool>,
+    remembered: RememberedDevices,
+}
+
+impl DevicePicker {
+    pub fn new(remembered: RememberedDevices) -> Self {
+        Self {
+            open: false,
+            scanning: watch::channel(false).0,
+            remembered,
+        }
+    }
+
+    /// Whether the panel is open, and with it whether to scan.
+    pub fn scan_requests(&self) -> watch::Receiver<bool> {
+        self.scanning.subscribe()
+    }
+
+    fn set_open(&mut self, open: bool) {
+        self.open = open;
+        self.scanning.send_replace(open);
+    }
+}
+
+/// A device seen while scanning.
+#[derive(Debug, Clone)]
+pub struct ScannedDevice {
+    pub address: String,
+    pub name: Option<String>,
+    pub rssi: Option<i16>,
+    pub services: Vec<Uuid>,
+}
+
+/// Everything seen since the panel was opened, in the order it was first seen.
+#[derive(Resource, Default)]
+pub struct ScanResults {
+    pub devices: Vec<ScannedDevice>,
+}
+
+impl ScanResults {
+    pub fn update(&mut self, device: ScannedDevice) {
+        match self.devices.iter_mut().find(|known| known.address == device.address) {
+            Some(known) => *known = device,
+            None => self.devices.push(device),
+        }
+    }
+}
+
+fn toggle_device_picker(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut picker: ResMut<DevicePicker>,
+    mut results: ResMut<ScanResults>,
+) {
+    if !keys.just_pressed(KeyCode::KeyB) {
+        return;
+    }
+    let open = !picker.open;
+    if open {
+        // Start from a fresh scan so devices that went away don't linger
+        results.devices.clear();
+    }
+    picker.set_open(open);
+}
+
+fn show_device_picker(
+    mut contexts: EguiContexts,
+    mut picker: ResMut<DevicePicker>,
+    results: Res<ScanResults>,
+    config: Res<GloveConfig>,
+    gloves: Query<(&Handedness, &GloveConnection, &DeviceInfo, &BleControlQueue)>,
+) {
+    if !picker.open {
+        return;
+    }
+
+    let mut gloves: Vec<_> = gloves.iter().collect();
+    gloves.sort_by_key(|(hand, _, _, _)| **hand as u8);
+
+    let mut open = true;
+    let mut picked: Vec<(Handedness, BleControl)> = Vec::new();
+    egui::Window::new("Bluetooth devices").open(&mut open).show(contexts.ctx_mut(), |ui| {
+        if results.devices.is_empty() {
+            ui.label("Scanning...");
+            return;
+        }
+
+        egui::Grid::new("scanned_devices").striped(true).show(ui, |ui| {
+            ui.strong("Name");
+            ui.strong("Address");
+            ui.strong("RSSI");
+            ui.strong("Services");
+            ui.end_row();
+
+            for device in results.devices.iter() {
+                ui.label(device.name.as_deref().unwrap_or("(unnamed)"));
+                ui.label(device.address.as_str());
+                ui.label(device.rssi.map(|rssi| format!("{} dBm", rssi)).unwrap_or_default());
+                ui.label(device.services.iter().map(|uuid| uuid.to_short_string()).collect::<Vec<_>>().join(", "));
+
+                ui.horizontal(|ui| {
+                    for (hand, connection, info, _) in gloves.iter() {
+                        let is_connected = !matches!(connection, GloveConnection::Idle | GloveConnection::Disconnected)
+                            && info.address.as_ref().is_some_and(|address| address.eq_ignore_ascii_case(&device.address));
+                        if is_connected {
+                            if ui.button(format!("Disconnect {:?}", hand)).clicked() {
+                                picked.push((**hand, BleControl::Disconnect));
+                            }
+                        } else if ui.button(format!("Connect {:?}", hand)).clicked() {
+                            picked.push((**hand, BleControl::Connect { address: device.address.clone() }));
+                        }
+                    }
+                });
+                ui.end_row();
+            }
+        });
+    });
+    if !open {
+        picker.set_open(false);
+    }
+
+    for (hand, control) in picked {
+        let Some((_, _, _, BleControlQueue(queue))) = gloves.iter().find(|(glove_hand, _, _, _)| **glove_hand == hand) else {
+            continue;
+        };
+        let address = match &control {
+            BleControl::Connect { address } => Some(address.clone()),
+            BleControl::Disconnect => None,
+        };
+        if queue.send(control).is_err() {
+            continue;
+        }
+        picker.remembered.set(hand, address);
+        picker.remembered.save(&config.remembered_devices);
+    }
+}
@@ -0,0 +1,261 @@
+// Calibration profiles: what calibrating a glove found out, saved per user and per glove in
+// `calibrations` (calibrations.toml by default).
+//
+// A glove is known by its serial number, or its Bluetooth address if it doesn't report one,
+// and its calibration for the current user is put back whenever it starts streaming. Shared
+// gloves get one profile per user, switched between in a panel toggled with P. --profile picks
+// the user on startup, otherwise it's whoever was picked last.
+
+use std::path::{Path, PathBuf};
+
+use bevy::app::{App, Plugin, Update};
+use bevy::ecs::event::EventReader;
+use bevy::ecs::query::Changed;
+use bevy::ecs::system::{Query, Res, ResMut, Resource};
+use bevy::input::{keyboard::KeyCode, ButtonInput};
+use bevy::log::{error, info, warn};
+use bevy_inspector_egui::bevy_egui::{egui, EguiContexts, EguiPlugin};
+use serde::{Deserialize, Serialize};
+
+use crate::calibration::CalibrationChanged;
+use crate::config::GloveConfig;
+use crate::device::DeviceInfo;
+use crate::ruka::{GloveCalibration, GloveConnection, Handedness, RukaInput};
+
+const DEFAULT_USER: &str = "default";
+
+pub struct ProfilesPlugin;
+
+impl Plugin for ProfilesPlugin {
+    fn build(&self, app: &mut App) {
+        let config = app.world.resource::<GloveConfig>();
+        let store = CalibrationStore::load(&config.calibrations);
+        let user = config
+            .profile
+            .clone()
+            .or_else(|| store.user.clone())
+            .unwrap_or_else(|| String::from(DEFAULT_USER));
+        info!("Using calibration profile {}", user);
+
+        let profiles = Profiles {
+            path: config.calibrations.clone(),
+            store,
+            user,
+            open: false,
+            new_user: String::new(),
+        };
+        if !app.is_plugin_added::<EguiPlugin>() {
+            app.add_plugins(EguiPlugin);
+        }
+        app
+            .insert_resource(profiles)
+            .add_systems(Update, toggle_profiles)
+            .add_systems(Update, load_on_connect)
+            .add_systems(Update, save_calibration)
+            .add_systems(Update, show_profiles)
+        ;
+    }
+}
+
+/// Everything in the calibrations file.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CalibrationStore {
+    /// The user picked last
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default)]
+    pub profiles: Vec<CalibrationProfile>,
+}
+
+/// One user's calibration of one glove.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CalibrationProfile {
+    pub user: String,
+    /// See `glove_id`
+    pub glove: String,
+    pub calibration: GloveCalibration,
+}
+
+impl CalibrationStore {
+    /// A missing file just means nothing has been calibrated yet.
+    pub fn load(path: &Path) -> Self {
+        let Ok(contents) = std::fs::read_to_string(path) else {
+            return CalibrationStore::default();
+        };
+        match toml::from_str::<CalibrationStore>(&contents) {
+            Ok(store) => store,
+            Err(err) => {
+                warn!("Error parsing {:?}, starting without calibrations: {}", path, err);
+                CalibrationStore::default()
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) {
+        let contents = match toml::to_string(self) {
+            Ok(contents) => contents,
+            Err(err) => {
+                error!("Error writing calibrations: {}", err);
+                return;
+            }
+        };
+        let contents = format!("# Written by the app: glove calibrations per user, see src/profiles.rs\n\n{}", contents);
+        if let Err(err) = std::fs::write(path, contents) {
+            error!("Error saving calibrations to {:?}: {}", path, err);
+        }
+    }
+
+    pub fn get(&self, user: &str, glove: &str) -> Option<&GloveCalibration> {
+        self.profiles
+            .iter()
+            .find(|profile| profile.user == user && profile.glove == glove)
+            .map(|profile| &profile.calibration)
+    }
+
+    /// Replaces the user's calibration of the glove, or forgets it if there is none.
+    pub fn set(&mut self, user: &str, glove: &str, calibration: Option<GloveCalibration>) {
+        self.profiles.retain(|profile| profile.user != user || profile.glove != glove);
+        if let Some(calibration) = calibration {
+            self.profiles.push(CalibrationProfile {
+                user: user.to_string(),
+                glove: glove.to_string(),
+                calibration,
+            });
+        }
+    }
+
+    /// Everyone with a saved calibration, plus `current`.
+    pub fn users(&self, current: &str) -> Vec<String> {
+        let mut users: Vec<String> = self.profiles.iter().map(|profile| profile.user.clone()).collect();
+        users.push(current.to_string());
+        users.sort();
+        users.dedup();
+        users
+    }
+}
+
+/// What a glove's calibrations are saved under: its serial number if it reports one, else
+/// its address, else just the hand, for sources that know neither. Both are only ever from the
+/// current session, since `DeviceInfo` starts over whenever a glove connects.
+pub fn glove_id(hand: Handedness, info: &DeviceInfo) -> String {
+    info.serial_number
+        .clone()
+        .or_else(|| info.address.clone())
+        .unwrap_or_else(|| format!("{:?} glove", hand).to_lowercase())
+}
+
+#[derive(Resource)]
+pub struct Profiles {
+    path: PathBuf,
+    store: CalibrationStore,
+    /// Whose calibrations are in use
+    user: String,
+    open: bool,
+    /// Name typed into the panel for a new profile
+    new_user: String,
+}
+
+impl Profiles {
+    /// Switches to another user's calibrations, and remembers them for next time.
+    fn switch(&mut self, user: String, gloves: &mut Query<(&Handedness, &DeviceInfo, &mut RukaInput)>) {
+        info!("Switching to calibration profile {}", user);
+        for (hand, info, mut ruka) in gloves.iter_mut() {
+            match self.store.get(&user, &glove_id(*hand, info)) {
+                Some(calibration) => ruka.apply_calibration(calibration),
+                None => ruka.reset_calibration(),
+            }
+        }
+        self.store.user = Some(user.clone());
+        self.store.save(&self.path);
+        self.user = user;
+    }
+}
+
+fn toggle_profiles(keys: Res<ButtonInput<KeyCode>>, mut profiles: ResMut<Profiles>) {
+    if keys.just_pressed(KeyCode::KeyP) {
+        profiles.open = !profiles.open;
+    }
+}
+
+fn load_on_connect(
+    mut gloves: Query<(&Handedness, &GloveConnection, &DeviceInfo, &mut RukaInput), Changed<GloveConnection>>,
+    profiles: Res<Profiles>,
+) {
+    for (hand, connection, info, mut ruka) in gloves.iter_mut() {
+        if *connection != GloveConnection::Streaming {
+            continue;
+        }
+        let glove = glove_id(*hand, info);
+        if let Some(calibration) = profiles.store.get(&profiles.user, &glove) {
+            info!("Loaded {}'s calibration of {:?} glove {}", profiles.user, hand, glove);
+            ruka.apply_calibration(calibration);
+        }
+    }
+}
+
+fn save_calibration(
+    mut changes: EventReader<CalibrationChanged>,
+    gloves: Query<(&Handedness, &DeviceInfo, &RukaInput)>,
+    mut profiles: ResMut<Profiles>,
+) {
+    let mut changed = false;
+    for CalibrationChanged { hand } in changes.read() {
+        let Some((_, info, ruka)) = gloves.iter().find(|(glove, _, _)| **glove == *hand) else {
+            continue;
+        };
+        let user = profiles.user.clone();
+        profiles.store.set(&user, &glove_id(*hand, info), ruka.calibration());
+        changed = true;
+    }
+    if changed {
+        profiles.store.save(&profiles.path);
+    }
+}
+
+fn show_profiles(
+    mut contexts: EguiContexts,
+    mut profiles: ResMut<Profiles>,
+    mut gloves: Query<(&Handedness, &DeviceInfo, &mut RukaInput)>,
+) {
+    if !profiles.open {
+        return;
+    }
+
+    let mut open = true;
+    let mut picked = None;
+    let profiles = &mut *profiles;
+    egui::Window::new("Calibration profiles").open(&mut open).show(contexts.ctx_mut(), |ui| {
+        for user in profiles.store.users(&profiles.user) {
+            let selected = user == profiles.user;
+            if ui.selectable_label(selected, user.as_str()).clicked() && !selected {
+                picked = Some(user);
+            }
+        }
+
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut profiles.new_user);
+            let name = profiles.new_user.trim();
+            if ui.add_enabled(!name.is_empty(), egui::Button::new("Add")).clicked() {
+                picked = Some(name.to_string());
+                profiles.new_user.clear();
+            }
+        });
+
+        ui.separator();
+        let mut gloves: Vec<_> = gloves.iter().collect();
+        gloves.sort_by_key(|(hand, _, _)| **hand as u8);
+        for (hand, info, _) in gloves {
+            let glove = glove_id(*hand, info);
+            let status = match profiles.store.get(&profiles.user, &glove) {
+                Some(_) => "calibrated",
+                None => "not calibrated, press K",
+            };
+            ui.label(format!("{:?} glove {}: {}", hand, glove, status));
+        }
+    });
+    profiles.open = open;
+
+    if let Some(user) = picked {
+        profiles.switch(user, &mut gloves);
+    }
+}
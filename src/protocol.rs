@@ -0,0 +1,268 @@
+// Low level wire format of the glove's sensor payloads. Which payloads exist and what
+// is in them is described by the channel schema (see schema.rs); this is the bytes side:
+// field types, byte order and what goes wrong while decoding.
+//
+// The firmware sends the same payloads over BLE, USB HID and serial, and net.rs relays
+// them unchanged. The current glove's two payloads, as the built-in schema describes them:
+//
+//   flex (10 bytes): five u16 ADC counts, thumb first
+//   imu  (12 bytes): accel xyz then gyro xyz, i16 scaled by IMU_SCALE
+
+use std::fmt;
+
+use serde::Deserialize;
+
+/// Bumped whenever the framing around payloads changes. Carried in the header of relayed packets.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+pub const BYTE_ORDER: ByteOrder = ByteOrder::Big;
+
+/// The firmware sends IMU readings multiplied by this, so 981 is 9.81 m/s².
+pub const IMU_SCALE: f32 = 100.0;
+
+/// Packet ids of the current glove's payloads: the type byte in framed transports (serial,
+/// UDP) and the HID report ID.
+pub const KIND_FLEX: u8 = 0x01;
+pub const KIND_IMU: u8 = 0x02;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ByteOrder {
+    #[default]
+    Big,
+    Little,
+}
+
+/// How a single channel value is stored in a payload.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+}
+
+impl FieldType {
+    pub fn size(self) -> usize {
+        match self {
+            FieldType::U8 | FieldType::I8 => 1,
+            FieldType::U16 | FieldType::I16 => 2,
+            FieldType::U32 | FieldType::I32 | FieldType::F32 => 4,
+        }
+    }
+
+    /// Reads one raw value. `bytes` must be exactly `size()` long. Every type fits in an f64
+    /// exactly, so nothing is lost until the caller narrows it.
+    pub fn read(self, bytes: &[u8], order: ByteOrder) -> f64 {
+        let mut word = [0u8; 4];
+        word[..bytes.len()].copy_from_slice(bytes);
+        if order == ByteOrder::Big {
+            word[..bytes.len()].reverse();
+        }
+        // `word` is little-endian from here on
+        match self {
+            FieldType::U8 => word[0] as f64,
+            FieldType::I8 => word[0] as i8 as f64,
+            FieldType::U16 => u16::from_le_bytes([word[0], word[1]]) as f64,
+            FieldType::I16 => i16::from_le_bytes([word[0], word[1]]) as f64,
+            FieldType::U32 => u32::from_le_bytes(word) as f64,
+            FieldType::I32 => i32::from_le_bytes(word) as f64,
+            FieldType::F32 => f32::from_le_bytes(word) as f64,
+        }
+    }
+
+    /// Writes one raw value, rounding and saturating it to fit the type.
+    pub fn write(self, value: f64, order: ByteOrder, out: &mut Vec<u8>) {
+        let word = match self {
+            FieldType::U8 => (value.round() as u8 as u32).to_le_bytes(),
+            FieldType::I8 => (value.round() as i8 as u8 as u32).to_le_bytes(),
+            FieldType::U16 => (value.round() as u16 as u32).to_le_bytes(),
+            FieldType::I16 => (value.round() as i16 as u16 as u32).to_le_bytes(),
+            FieldType::U32 => (value.round() as u32).to_le_bytes(),
+            FieldType::I32 => (value.round() as i32).to_le_bytes(),
+            FieldType::F32 => (value as f32).to_le_bytes(),
+        };
+        let mut bytes = word[..self.size()].to_vec();
+        if order == ByteOrder::Big {
+            bytes.reverse();
+        }
+        out.extend_from_slice(&bytes);
+    }
+}
+
+/// One decoded payload: which schema packet it was and the value of each of its channels,
+/// already scaled to real units.
+#[derive(Debug, Clone)]
+pub struct SampleData {
+    pub packet: u8,
+    pub values: Vec<f32>,
+    /// The packet's timestamp channel in milliseconds, if it has one. Also in `values`, but an
+    /// f32 can't tell milliseconds apart after a few hours of uptime.
+    pub timestamp: Option<f64>,
+    /// The payload as it came from the glove, or the line for the serial line protocol
+    pub raw: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    WrongLength { packet: u8, expected: usize, actual: usize },
+    UnknownPacket(u8),
+    UnsupportedVersion(u8),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::WrongLength { packet, expected, actual } => {
+                write!(f, "packet {:02x} is {} bytes, expected {}", packet, actual, expected)
+            }
+            DecodeError::UnknownPacket(packet) => write!(f, "unknown packet {:02x}", packet),
+            DecodeError::UnsupportedVersion(version) => {
+                write!(f, "protocol version {}, expected {}", version, PROTOCOL_VERSION)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+pub fn check_version(version: u8) -> Result<(), DecodeError> {
+    if version != PROTOCOL_VERSION {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{ChannelGroup, ChannelRole, GloveSchema, PacketSchema};
+
+    const FLEX_LEN: usize = 10;
+    const IMU_LEN: usize = 12;
+
+    /// Equal to within what goes missing in fixed point.
+    fn close(actual: &[f32], expected: &[f32]) -> bool {
+        actual.len() == expected.len() && actual.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-4)
+    }
+
+    #[test]
+    fn decodes_flex() {
+        let data = [0x3A, 0x98, 0x00, 0x00, 0xFF, 0xFF, 0x01, 0x02, 0x00, 0x0A];
+        let flex = GloveSchema::default().decode(KIND_FLEX, &data).unwrap();
+        assert_eq!(flex.values, [15000.0, 0.0, 65535.0, 258.0, 10.0]);
+        assert_eq!(flex.raw, data);
+    }
+
+    #[test]
+    fn decodes_imu() {
+        // 981, -981, 0 then 4500, -1, 0
+        let data = [0x03, 0xD5, 0xFC, 0x2B, 0x00, 0x00, 0x11, 0x94, 0xFF, 0xFF, 0x00, 0x00];
+        let imu = GloveSchema::default().decode(KIND_IMU, &data).unwrap();
+        assert!(close(&imu.values, &[9.81, -9.81, 0.0, 45.0, -0.01, 0.0]), "{:?}", imu.values);
+    }
+
+    #[test]
+    fn rejects_wrong_length() {
+        let schema = GloveSchema::default();
+        assert_eq!(
+            schema.decode(KIND_FLEX, &[0; FLEX_LEN - 1]).unwrap_err(),
+            DecodeError::WrongLength { packet: KIND_FLEX, expected: FLEX_LEN, actual: FLEX_LEN - 1 },
+        );
+        assert_eq!(
+            schema.decode(KIND_IMU, &[0; IMU_LEN + 2]).unwrap_err(),
+            DecodeError::WrongLength { packet: KIND_IMU, expected: IMU_LEN, actual: IMU_LEN + 2 },
+        );
+        // A flex payload is not an IMU payload, even though both are a run of 16 bit words
+        assert!(schema.decode(KIND_IMU, &[0; FLEX_LEN]).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_packet() {
+        assert_eq!(GloveSchema::default().decode(0x7F, &[]).unwrap_err(), DecodeError::UnknownPacket(0x7F));
+    }
+
+    #[test]
+    fn checks_protocol_version() {
+        assert_eq!(check_version(PROTOCOL_VERSION), Ok(()));
+        assert_eq!(check_version(PROTOCOL_VERSION + 1), Err(DecodeError::UnsupportedVersion(PROTOCOL_VERSION + 1)));
+        assert_eq!(check_version(0), Err(DecodeError::UnsupportedVersion(0)));
+    }
+
+    #[test]
+    fn flex_round_trip() {
+        let flex = GloveSchema::default().by_id(KIND_FLEX).cloned().unwrap();
+        let values = [0.0, 1.0, 15000.0, 32768.0, 65535.0];
+        let data = flex.encode(&values);
+        assert_eq!(data.len(), FLEX_LEN);
+        // Big-endian on the wire
+        assert_eq!(data[4..6], [0x3A, 0x98]);
+        assert_eq!(flex.decode(&data).unwrap().values, values);
+    }
+
+    #[test]
+    fn imu_round_trip() {
+        let imu = GloveSchema::default().by_id(KIND_IMU).cloned().unwrap();
+        let values = [0.0, -9.81, 9.81, -45.5, 0.01, 327.67];
+        let data = imu.encode(&values);
+        assert_eq!(data.len(), IMU_LEN);
+        let decoded = imu.decode(&data).unwrap().values;
+        assert!(close(&decoded, &values), "{:?}", decoded);
+    }
+
+    #[test]
+    fn field_types_round_trip() {
+        let cases = [
+            (FieldType::U8, 200.0),
+            (FieldType::I8, -100.0),
+            (FieldType::U16, 60000.0),
+            (FieldType::I16, -30000.0),
+            (FieldType::U32, 4_000_000.0),
+            (FieldType::I32, -4_000_000.0),
+            (FieldType::F32, 1.5),
+        ];
+        for order in [ByteOrder::Big, ByteOrder::Little] {
+            for (field_type, value) in cases {
+                let mut bytes = Vec::new();
+                field_type.write(value, order, &mut bytes);
+                assert_eq!(bytes.len(), field_type.size());
+                assert_eq!(field_type.read(&bytes, order), value, "{:?} {:?}", field_type, order);
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_timestamps_exact() {
+        let packet = PacketSchema {
+            name: String::from("timed"),
+            id: 0x03,
+            uuid: None,
+            tag: None,
+            byte_order: BYTE_ORDER,
+            channels: vec![ChannelGroup {
+                names: vec![String::from("t")],
+                field_type: FieldType::U32,
+                scale: 1.0,
+                units: String::from("ms"),
+                role: Some(ChannelRole::Timestamp),
+            }],
+        };
+        // Just over 4.66 hours, the first millisecond an f32 can't hold
+        let ms = (1u32 << 24) + 1;
+        let data = packet.decode(&ms.to_be_bytes()).unwrap();
+        assert_eq!(data.timestamp, Some(ms as f64));
+        assert_ne!(data.values[0] as f64, ms as f64);
+        assert_eq!(packet.encode_sample(&data), ms.to_be_bytes());
+    }
+
+    #[test]
+    fn writes_saturate() {
+        let mut bytes = Vec::new();
+        FieldType::I16.write(40000.0, ByteOrder::Big, &mut bytes);
+        assert_eq!(bytes, [0x7F, 0xFF]);
+    }
+}
@@ -0,0 +1,166 @@
+// Session recorder: writes every sample and connection change to a file in `recordings`,
+// toggled with F9 or the button in the corner. See recording.rs for the format, and
+// `--export` for turning a recording into CSV or JSON lines.
+
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::app::{App, AppExit, Last, Plugin, Update};
+use bevy::ecs::event::{Event, EventReader, EventWriter};
+use bevy::ecs::query::Changed;
+use bevy::ecs::system::{Query, Res, ResMut, Resource};
+use bevy::input::{keyboard::KeyCode, ButtonInput};
+use bevy::log::{error, info};
+use bevy_inspector_egui::bevy_egui::{egui, EguiContexts, EguiPlugin};
+
+use crate::config::GloveConfig;
+use crate::recording::RecordingWriter;
+use crate::ruka::{GloveConnection, Handedness};
+use crate::schema::GloveSchema;
+use crate::transport::GloveSample;
+
+pub struct RecorderPlugin;
+
+impl Plugin for RecorderPlugin {
+    fn build(&self, app: &mut App) {
+        let dir = app.world.resource::<GloveConfig>().recordings.clone();
+        if !app.is_plugin_added::<EguiPlugin>() {
+            app.add_plugins(EguiPlugin);
+        }
+        app
+            .insert_resource(Recorder { dir, active: None })
+            .add_event::<ToggleRecording>()
+            .add_systems(Update, recording_keys)
+            .add_systems(Update, show_recorder)
+            .add_systems(Update, toggle_recording)
+            .add_systems(Update, record)
+            .add_systems(Last, finish_on_exit)
+        ;
+    }
+}
+
+/// Starts recording if the recorder is idle, stops it otherwise.
+#[derive(Event)]
+pub struct ToggleRecording;
+
+#[derive(Resource)]
+pub struct Recorder {
+    /// Where new recordings go
+    dir: PathBuf,
+    active: Option<ActiveRecording>,
+}
+
+struct ActiveRecording {
+    writer: RecordingWriter,
+    path: PathBuf,
+    samples: u64,
+}
+
+impl Recorder {
+    fn start(&mut self, schema: &GloveSchema, gloves: &Query<(&Handedness, &GloveConnection)>) {
+        if let Err(err) = std::fs::create_dir_all(&self.dir) {
+            error!("Can't create {:?} for recordings: {}", self.dir, err);
+            return;
+        }
+        let started = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let path = self.dir.join(format!("session-{}.glrec", started));
+        let mut writer = match RecordingWriter::create(&path, schema) {
+            Ok(writer) => writer,
+            Err(err) => {
+                error!("Can't start recording to {:?}: {}", path, err);
+                return;
+            }
+        };
+
+        // So the recording knows where every glove was at the start
+        for (hand, connection) in gloves.iter() {
+            if let Err(err) = writer.connection(*hand, *connection) {
+                error!("Can't start recording to {:?}: {}", path, err);
+                return;
+            }
+        }
+        info!("Recording to {:?}", path);
+        self.active = Some(ActiveRecording { writer, path, samples: 0 });
+    }
+
+    fn stop(&mut self) {
+        let Some(ActiveRecording { writer, path, samples }) = self.active.take() else {
+            return;
+        };
+        match writer.finish() {
+            Ok(()) => info!("Saved {} samples to {:?}", samples, path),
+            Err(err) => error!("Error finishing recording {:?}: {}", path, err),
+        }
+    }
+}
+
+fn recording_keys(keys: Res<ButtonInput<KeyCode>>, mut toggles: EventWriter<ToggleRecording>) {
+    if keys.just_pressed(KeyCode::F9) {
+        toggles.send(ToggleRecording);
+    }
+}
+
+fn show_recorder(mut contexts: EguiContexts, recorder: Res<Recorder>, mut toggles: EventWriter<ToggleRecording>) {
+    egui::Area::new(egui::Id::new("recorder"))
+        .anchor(egui::Align2::RIGHT_TOP, egui::vec2(-10.0, 10.0))
+        .show(contexts.ctx_mut(), |ui| {
+            let clicked = match recorder.active.as_ref() {
+                Some(active) => {
+                    ui.colored_label(egui::Color32::RED, format!("Recording, {} samples", active.samples));
+                    ui.button("Stop (F9)").clicked()
+                }
+                None => ui.button("Record (F9)").clicked(),
+            };
+            if clicked {
+                toggles.send(ToggleRecording);
+            }
+        });
+}
+
+fn toggle_recording(
+    mut toggles: EventReader<ToggleRecording>,
+    mut recorder: ResMut<Recorder>,
+    schema: Res<GloveSchema>,
+    gloves: Query<(&Handedness, &GloveConnection)>,
+) {
+    // Two toggles in one frame cancel out
+    if toggles.read().count() % 2 == 0 {
+        return;
+    }
+    match recorder.active.is_some() {
+        true => recorder.stop(),
+        false => recorder.start(&schema, &gloves),
+    }
+}
+
+/// Writes everything that came in this frame to the active recording, if there is one.
+fn record(
+    mut recorder: ResMut<Recorder>,
+    mut samples: EventReader<GloveSample>,
+    connections: Query<(&Handedness, &GloveConnection), Changed<GloveConnection>>,
+) {
+    let Some(active) = recorder.active.as_mut() else {
+        samples.clear();
+        return;
+    };
+
+    let mut written = Ok(());
+    for (hand, connection) in connections.iter() {
+        written = written.and_then(|_| active.writer.connection(*hand, *connection));
+    }
+    for sample in samples.read() {
+        written = written.and_then(|_| active.writer.sample(sample));
+        active.samples += 1;
+    }
+
+    if let Err(err) = written {
+        error!("Error writing to {:?}, stopping the recording: {}", active.path, err);
+        recorder.stop();
+    }
+}
+
+fn finish_on_exit(exits: EventReader<AppExit>, mut recorder: ResMut<Recorder>) {
+    if !exits.is_empty() {
+        recorder.stop();
+    }
+}
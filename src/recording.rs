@@ -0,0 +1,640 @@
+// Recorded glove sessions: the file format, and exporting it for analysis.
+//
+// A recording starts with a header: magic, format version, when it was started and the
+// packet layout of the schema it was recorded with (ids, names and channel roles). After that
+// it's a flat run of records, each starting with a tag byte. All numbers are big-endian.
+//
+//   sample:      tag 1, hand u8, seq u64, t_host u64 µs, t_device u64 µs (u64::MAX if none),
+//                packet u8, value count u16, values f32, raw length u16, raw payload
+//   connection:  tag 2, hand u8, t_host u64 µs, state u8
+//
+// t_host is relative to the start of the recording. Values are stored decoded, exactly as the
+// app saw them, so a recording plays back the same even if the schema's scales change later.
+// The raw payload is kept next to them, as the glove sent it, so a recording can still be
+// decoded again after fixing a schema. Version 1 recordings have no raw payloads.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json::json;
+
+use crate::protocol::SampleData;
+use crate::ruka::{GloveConnection, Handedness};
+use crate::schema::{ChannelRole, GloveSchema};
+use crate::transport::GloveSample;
+
+const MAGIC: [u8; 8] = *b"GLOVEREC";
+/// Bumped whenever the layout above changes.
+pub const RECORDING_VERSION: u16 = 2;
+
+const TAG_SAMPLE: u8 = 1;
+const TAG_CONNECTION: u8 = 2;
+const NO_DEVICE_TIME: u64 = u64::MAX;
+
+/// What a recording knows about the schema it was made with.
+#[derive(Debug, Clone)]
+pub struct RecordingHeader {
+    /// Milliseconds since the Unix epoch
+    pub started_ms: u64,
+    pub packets: Vec<RecordedPacket>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordedPacket {
+    pub id: u8,
+    pub name: String,
+    pub channels: Vec<RecordedChannel>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordedChannel {
+    pub name: String,
+    pub role: Option<ChannelRole>,
+}
+
+impl RecordingHeader {
+    fn new(schema: &GloveSchema) -> Self {
+        let started_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        Self {
+            started_ms,
+            packets: schema
+                .packets
+                .iter()
+                .map(|packet| RecordedPacket {
+                    id: packet.id,
+                    name: packet.name.clone(),
+                    channels: packet
+                        .channels()
+                        .map(|channel| RecordedChannel { name: channel.name.to_string(), role: channel.role })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn packet(&self, id: u8) -> Option<&RecordedPacket> {
+        self.packets.iter().find(|packet| packet.id == id)
+    }
+
+    /// Whether samples from this recording mean the same thing under `schema`.
+    pub fn matches(&self, schema: &GloveSchema) -> bool {
+        let current = RecordingHeader::new(schema);
+        self.packets.len() == current.packets.len()
+            && self.packets.iter().zip(current.packets.iter()).all(|(recorded, current)| {
+                recorded.id == current.id
+                    && recorded.channels.len() == current.channels.len()
+                    && recorded
+                        .channels
+                        .iter()
+                        .zip(current.channels.iter())
+                        .all(|(a, b)| a.name == b.name && a.role == b.role)
+            })
+    }
+}
+
+impl RecordedPacket {
+    fn role_values(&self, values: &[f32], role: ChannelRole) -> Vec<f32> {
+        self.channels
+            .iter()
+            .zip(values)
+            .filter(|(channel, _)| channel.role == Some(role))
+            .map(|(_, value)| *value)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Record {
+    Sample(RecordedSample),
+    Connection {
+        hand: Handedness,
+        t_host: Duration,
+        state: GloveConnection,
+    },
+}
+
+impl Record {
+    pub fn hand(&self) -> Handedness {
+        match self {
+            Record::Sample(sample) => sample.hand,
+            Record::Connection { hand, .. } => *hand,
+        }
+    }
+
+    /// When the record was made, since the start of the recording.
+    pub fn t_host(&self) -> Duration {
+        match self {
+            Record::Sample(sample) => sample.t_host,
+            Record::Connection { t_host, .. } => *t_host,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordedSample {
+    pub hand: Handedness,
+    pub seq: u64,
+    pub t_host: Duration,
+    pub t_device: Option<Duration>,
+    pub data: SampleData,
+}
+
+#[derive(Debug)]
+pub enum RecordingError {
+    Io(io::Error),
+    NotARecording,
+    /// Made by a newer version of the app
+    UnsupportedVersion(u16),
+    Corrupt(String),
+}
+
+impl fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordingError::Io(err) => write!(f, "{}", err),
+            RecordingError::NotARecording => write!(f, "not a glove recording"),
+            RecordingError::UnsupportedVersion(version) => {
+                write!(f, "recording format version {} is not supported (up to {})", version, RECORDING_VERSION)
+            }
+            RecordingError::Corrupt(what) => write!(f, "corrupt recording: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for RecordingError {}
+
+impl From<io::Error> for RecordingError {
+    fn from(err: io::Error) -> Self {
+        RecordingError::Io(err)
+    }
+}
+
+fn hand_byte(hand: Handedness) -> u8 {
+    match hand {
+        Handedness::Left => 0,
+        Handedness::Right => 1,
+    }
+}
+
+fn hand_from_byte(byte: u8) -> Result<Handedness, RecordingError> {
+    match byte {
+        0 => Ok(Handedness::Left),
+        1 => Ok(Handedness::Right),
+        other => Err(RecordingError::Corrupt(format!("unknown hand {}", other))),
+    }
+}
+
+fn role_byte(role: Option<ChannelRole>) -> u8 {
+    match role {
+        None => 0,
+        Some(ChannelRole::Flex) => 1,
+        Some(ChannelRole::Accel) => 2,
+        Some(ChannelRole::Gyro) => 3,
+        Some(ChannelRole::Timestamp) => 4,
+    }
+}
+
+fn role_from_byte(byte: u8) -> Result<Option<ChannelRole>, RecordingError> {
+    match byte {
+        0 => Ok(None),
+        1 => Ok(Some(ChannelRole::Flex)),
+        2 => Ok(Some(ChannelRole::Accel)),
+        3 => Ok(Some(ChannelRole::Gyro)),
+        4 => Ok(Some(ChannelRole::Timestamp)),
+        other => Err(RecordingError::Corrupt(format!("unknown channel role {}", other))),
+    }
+}
+
+fn connection_byte(state: GloveConnection) -> u8 {
+    match state {
+        GloveConnection::Idle => 0,
+        GloveConnection::Scanning => 1,
+        GloveConnection::Connecting => 2,
+        GloveConnection::DiscoveringServices => 3,
+        GloveConnection::Streaming => 4,
+        GloveConnection::Disconnected => 5,
+    }
+}
+
+fn connection_from_byte(byte: u8) -> Result<GloveConnection, RecordingError> {
+    match byte {
+        0 => Ok(GloveConnection::Idle),
+        1 => Ok(GloveConnection::Scanning),
+        2 => Ok(GloveConnection::Connecting),
+        3 => Ok(GloveConnection::DiscoveringServices),
+        4 => Ok(GloveConnection::Streaming),
+        5 => Ok(GloveConnection::Disconnected),
+        other => Err(RecordingError::Corrupt(format!("unknown connection state {}", other))),
+    }
+}
+
+fn micros(duration: Duration) -> u64 {
+    duration.as_micros().min(NO_DEVICE_TIME as u128 - 1) as u64
+}
+
+fn write_str(out: &mut Vec<u8>, text: &str) {
+    let bytes = &text.as_bytes()[..text.len().min(u16::MAX as usize)];
+    out.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    out.extend_from_slice(bytes);
+}
+
+/// Writes a recording as it happens.
+pub struct RecordingWriter {
+    out: BufWriter<File>,
+    started: Instant,
+    /// Reused between records
+    buf: Vec<u8>,
+}
+
+impl RecordingWriter {
+    /// Creates the file and writes the header.
+    pub fn create(path: &Path, schema: &GloveSchema) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        let header = RecordingHeader::new(schema);
+
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&MAGIC);
+        buf.extend_from_slice(&RECORDING_VERSION.to_be_bytes());
+        buf.extend_from_slice(&header.started_ms.to_be_bytes());
+        buf.push(header.packets.len() as u8);
+        for packet in header.packets.iter() {
+            buf.push(packet.id);
+            write_str(&mut buf, &packet.name);
+            buf.extend_from_slice(&(packet.channels.len() as u16).to_be_bytes());
+            for channel in packet.channels.iter() {
+                write_str(&mut buf, &channel.name);
+                buf.push(role_byte(channel.role));
+            }
+        }
+        out.write_all(&buf)?;
+
+        Ok(Self {
+            out,
+            started: Instant::now(),
+            buf,
+        })
+    }
+
+    pub fn sample(&mut self, sample: &GloveSample) -> io::Result<()> {
+        let t_host = sample.t_host.saturating_duration_since(self.started);
+        let t_device = sample.t_device.map(micros).unwrap_or(NO_DEVICE_TIME);
+
+        self.buf.clear();
+        self.buf.push(TAG_SAMPLE);
+        self.buf.push(hand_byte(sample.hand));
+        self.buf.extend_from_slice(&sample.seq.to_be_bytes());
+        self.buf.extend_from_slice(&micros(t_host).to_be_bytes());
+        self.buf.extend_from_slice(&t_device.to_be_bytes());
+        self.buf.push(sample.data.packet);
+        self.buf.extend_from_slice(&(sample.data.values.len() as u16).to_be_bytes());
+        for value in sample.data.values.iter() {
+            self.buf.extend_from_slice(&value.to_be_bytes());
+        }
+        let raw = &sample.data.raw[..sample.data.raw.len().min(u16::MAX as usize)];
+        self.buf.extend_from_slice(&(raw.len() as u16).to_be_bytes());
+        self.buf.extend_from_slice(raw);
+        self.out.write_all(&self.buf)
+    }
+
+    pub fn connection(&mut self, hand: Handedness, state: GloveConnection) -> io::Result<()> {
+        self.buf.clear();
+        self.buf.push(TAG_CONNECTION);
+        self.buf.push(hand_byte(hand));
+        self.buf.extend_from_slice(&micros(self.started.elapsed()).to_be_bytes());
+        self.buf.push(connection_byte(state));
+        self.out.write_all(&self.buf)
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Reads a recording back, record by record.
+pub struct RecordingReader<R> {
+    input: R,
+    /// Format version the recording was written with
+    version: u16,
+    pub header: RecordingHeader,
+}
+
+impl RecordingReader<BufReader<File>> {
+    pub fn open(path: &Path) -> Result<Self, RecordingError> {
+        RecordingReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> RecordingReader<R> {
+    pub fn new(mut input: R) -> Result<Self, RecordingError> {
+        let mut magic = [0u8; 8];
+        input.read_exact(&mut magic).map_err(|_| RecordingError::NotARecording)?;
+        if magic != MAGIC {
+            return Err(RecordingError::NotARecording);
+        }
+        let version = u16::from_be_bytes(read_array(&mut input)?);
+        if !(1..=RECORDING_VERSION).contains(&version) {
+            return Err(RecordingError::UnsupportedVersion(version));
+        }
+
+        let started_ms = u64::from_be_bytes(read_array(&mut input)?);
+        let [packet_count] = read_array(&mut input)?;
+        let mut packets = Vec::with_capacity(packet_count as usize);
+        for _ in 0..packet_count {
+            let [id] = read_array(&mut input)?;
+            let name = read_str(&mut input)?;
+            let channel_count = u16::from_be_bytes(read_array(&mut input)?);
+            let mut channels = Vec::with_capacity(channel_count as usize);
+            for _ in 0..channel_count {
+                let name = read_str(&mut input)?;
+                let [role] = read_array(&mut input)?;
+                channels.push(RecordedChannel { name, role: role_from_byte(role)? });
+            }
+            packets.push(RecordedPacket { id, name, channels });
+        }
+
+        Ok(Self {
+            input,
+            version,
+            header: RecordingHeader { started_ms, packets },
+        })
+    }
+
+    /// The next record, or None at the end. A record cut off halfway, e.g. because the app
+    /// crashed while recording, also counts as the end.
+    pub fn next_record(&mut self) -> Result<Option<Record>, RecordingError> {
+        let mut tag = [0u8; 1];
+        if self.input.read(&mut tag)? == 0 {
+            return Ok(None);
+        }
+        match self.read_record(tag[0]) {
+            Ok(record) => Ok(Some(record)),
+            Err(RecordingError::Io(err)) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn read_record(&mut self, tag: u8) -> Result<Record, RecordingError> {
+        let input = &mut self.input;
+        match tag {
+            TAG_SAMPLE => {
+                let [hand] = read_array(input)?;
+                let seq = u64::from_be_bytes(read_array(input)?);
+                let t_host = u64::from_be_bytes(read_array(input)?);
+                let t_device = u64::from_be_bytes(read_array(input)?);
+                let [packet] = read_array(input)?;
+                let count = u16::from_be_bytes(read_array(input)?);
+                let mut values = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    values.push(f32::from_be_bytes(read_array(input)?));
+                }
+                let mut raw = Vec::new();
+                if self.version >= 2 {
+                    raw.resize(u16::from_be_bytes(read_array(input)?) as usize, 0);
+                    input.read_exact(&mut raw)?;
+                }
+                // t_device is the glove's clock (its timestamp channel, or a relay's), kept to the microsecond
+                let t_device = (t_device != NO_DEVICE_TIME).then(|| Duration::from_micros(t_device));
+                Ok(Record::Sample(RecordedSample {
+                    hand: hand_from_byte(hand)?,
+                    seq,
+                    t_host: Duration::from_micros(t_host),
+                    t_device,
+                    data: SampleData {
+                        packet,
+                        values,
+                        timestamp: t_device.map(|t| t.as_micros() as f64 / 1000.0),
+                        raw,
+                    },
+                }))
+            }
+            TAG_CONNECTION => {
+                let [hand] = read_array(input)?;
+                let t_host = u64::from_be_bytes(read_array(input)?);
+                let [state] = read_array(input)?;
+                Ok(Record::Connection {
+                    hand: hand_from_byte(hand)?,
+                    t_host: Duration::from_micros(t_host),
+                    state: connection_from_byte(state)?,
+                })
+            }
+            other => Err(RecordingError::Corrupt(format!("unknown record type {}", other))),
+        }
+    }
+}
+
+/// Reads a whole recording into memory.
+pub fn read_all(path: &Path) -> Result<(RecordingHeader, Vec<Record>), RecordingError> {
+    let mut reader = RecordingReader::open(path)?;
+    let mut records = Vec::new();
+    while let Some(record) = reader.next_record()? {
+        records.push(record);
+    }
+    Ok((reader.header, records))
+}
+
+fn read_array<const N: usize>(input: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_str(input: &mut impl Read) -> Result<String, RecordingError> {
+    let len = u16::from_be_bytes(read_array(input)?);
+    let mut bytes = vec![0u8; len as usize];
+    input.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|_| RecordingError::Corrupt(String::from("name is not UTF-8")))
+}
+
+/// Formats for `--export`.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExportFormat {
+    /// One row per record, one column per channel
+    #[default]
+    Csv,
+    /// One JSON object per record
+    Jsonl,
+}
+
+impl ExportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Jsonl => "jsonl",
+        }
+    }
+}
+
+fn hand_name(hand: Handedness) -> &'static str {
+    match hand {
+        Handedness::Left => "left",
+        Handedness::Right => "right",
+    }
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs_f64()
+}
+
+/// Raw payloads as text, e.g. "3a98ffff".
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Quotes a CSV field if it needs it.
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Converts a recording to a file next to it with the format's extension, e.g.
+/// session.glrec to session.csv. Returns where it went.
+pub fn export(path: &Path, format: ExportFormat) -> Result<PathBuf, RecordingError> {
+    let mut reader = RecordingReader::open(path)?;
+    let target = path.with_extension(format.extension());
+    let mut out = BufWriter::new(File::create(&target)?);
+
+    // Every channel of every packet gets a CSV column, in schema order
+    let columns: Vec<(u8, String)> = reader
+        .header
+        .packets
+        .iter()
+        .flat_map(|packet| packet.channels.iter().map(|channel| (packet.id, channel.name.clone())))
+        .collect();
+    if format == ExportFormat::Csv {
+        let names: Vec<String> = columns.iter().map(|(_, name)| csv_field(name)).collect();
+        writeln!(out, "kind,hand,seq,t_host,t_device,packet,raw,connection,{}", names.join(","))?;
+    }
+
+    while let Some(record) = reader.next_record()? {
+        let header = &reader.header;
+        match (format, record) {
+            (ExportFormat::Csv, Record::Sample(sample)) => {
+                let packet = header.packet(sample.data.packet);
+                let mut values = sample.data.values.iter();
+                let cells: Vec<String> = columns
+                    .iter()
+                    .map(|(id, _)| match *id == sample.data.packet {
+                        true => values.next().map(|value| value.to_string()).unwrap_or_default(),
+                        false => String::new(),
+                    })
+                    .collect();
+                writeln!(
+                    out,
+                    "sample,{},{},{},{},{},{},,{}",
+                    hand_name(sample.hand),
+                    sample.seq,
+                    seconds(sample.t_host),
+                    sample.t_device.map(|t| seconds(t).to_string()).unwrap_or_default(),
+                    csv_field(packet.map(|packet| packet.name.as_str()).unwrap_or_default()),
+                    hex(&sample.data.raw),
+                    cells.join(","),
+                )?;
+            }
+            (ExportFormat::Csv, Record::Connection { hand, t_host, state }) => {
+                writeln!(out, "connection,{},,{},,,,{:?},{}", hand_name(hand), seconds(t_host), state, ",".repeat(columns.len().saturating_sub(1)))?;
+            }
+            (ExportFormat::Jsonl, Record::Sample(sample)) => {
+                let packet = header.packet(sample.data.packet);
+                let values: serde_json::Map<String, serde_json::Value> = packet
+                    .map(|packet| {
+                        packet
+                            .channels
+                            .iter()
+                            .zip(sample.data.values.iter())
+                            .map(|(channel, value)| (channel.name.clone(), json!(value)))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let role = |role| packet.map(|packet| packet.role_values(&sample.data.values, role)).filter(|values| !values.is_empty());
+                let line = json!({
+                    "kind": "sample",
+                    "hand": hand_name(sample.hand),
+                    "seq": sample.seq,
+                    "t_host": seconds(sample.t_host),
+                    "t_device": sample.t_device.map(seconds),
+                    "packet": packet.map(|packet| packet.name.as_str()),
+                    "raw": hex(&sample.data.raw),
+                    "flex": role(ChannelRole::Flex),
+                    "accel": role(ChannelRole::Accel),
+                    "gyro": role(ChannelRole::Gyro),
+                    "values": values,
+                });
+                writeln!(out, "{}", line)?;
+            }
+            (ExportFormat::Jsonl, Record::Connection { hand, t_host, state }) => {
+                let line = json!({
+                    "kind": "connection",
+                    "hand": hand_name(hand),
+                    "t_host": seconds(t_host),
+                    "connection": format!("{:?}", state),
+                });
+                writeln!(out, "{}", line)?;
+            }
+        }
+    }
+    out.flush()?;
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::KIND_FLEX;
+
+    #[test]
+    fn reads_back_what_was_written() {
+        let schema = GloveSchema::default();
+        let path = std::env::temp_dir().join(format!("glove-debug-test-{}.glrec", std::process::id()));
+        let mut writer = RecordingWriter::create(&path, &schema).unwrap();
+
+        let payload = schema.by_id(KIND_FLEX).unwrap().encode(&[1000.0, 2000.0, 3000.0, 4000.0, 5000.0]);
+        let data = schema.decode(KIND_FLEX, &payload).unwrap();
+        // Past where an f32 of milliseconds runs out of precision
+        let ms = (1u64 << 24) + 1;
+        let sample = GloveSample {
+            hand: Handedness::Left,
+            seq: 42,
+            t_host: Instant::now(),
+            t_device: Some(Duration::from_millis(ms)),
+            flex: data.values.clone(),
+            imu: None,
+            data: SampleData {
+                timestamp: Some(ms as f64),
+                ..data
+            },
+        };
+        writer.connection(Handedness::Left, GloveConnection::Streaming).unwrap();
+        writer.sample(&sample).unwrap();
+        writer.finish().unwrap();
+
+        let read = read_all(&path);
+        std::fs::remove_file(&path).unwrap();
+        let (header, records) = read.unwrap();
+
+        assert!(header.matches(&schema));
+        assert_eq!(records.len(), 2);
+        assert!(matches!(
+            records[0],
+            Record::Connection { hand: Handedness::Left, state: GloveConnection::Streaming, .. }
+        ));
+        let Record::Sample(read) = &records[1] else {
+            panic!("expected a sample, got {:?}", records[1]);
+        };
+        assert_eq!(read.hand, sample.hand);
+        assert_eq!(read.seq, sample.seq);
+        assert_eq!(read.t_device, sample.t_device);
+        assert_eq!(read.data.packet, KIND_FLEX);
+        assert_eq!(read.data.values, sample.data.values);
+        assert_eq!(read.data.timestamp, Some(ms as f64));
+        assert_eq!(read.data.raw, payload);
+    }
+}
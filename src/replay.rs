@@ -0,0 +1,313 @@
+// Plays a recorded session back as a glove source, set with --replay or `replay` in glove.toml.
+//
+// Every recorded hand gets a transport that feeds its samples into a GloveSink like a live
+// glove would, so everything downstream can't tell the difference. All of them follow one
+// shared clock, which the timeline at the bottom of the window controls: play and pause
+// (also Space), scrubbing, looping and playback speed.
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use bevy::app::{App, Plugin, Startup, Update};
+use bevy::ecs::system::{Res, ResMut, Resource};
+use bevy::input::{keyboard::KeyCode, ButtonInput};
+use bevy::log::{error, info, warn};
+use bevy_inspector_egui::bevy_egui::{egui, EguiContexts, EguiPlugin};
+use tokio::time;
+
+use crate::asyncs::TokioTasksRuntime;
+use crate::config::{GloveConfig, GloveSource};
+use crate::recording::{self, Record};
+use crate::ruka::{GloveConnection, Handedness};
+use crate::schema::GloveSchema;
+use crate::transport::{spawn_transport, GloveSink, GloveTransport, SampleQueue};
+
+const MIN_SPEED: f32 = 0.25;
+const MAX_SPEED: f32 = 4.0;
+/// How often the transports catch up with the clock.
+const REPLAY_TICK: Duration = Duration::from_millis(2);
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        let Some(path) = app.world.resource::<GloveConfig>().replay.clone() else {
+            return;
+        };
+        let (header, records) = match recording::read_all(&path) {
+            Ok(recording) => recording,
+            Err(err) => {
+                error!("Can't replay {:?}: {}", path, err);
+                return;
+            }
+        };
+        if !header.matches(app.world.resource::<GloveSchema>()) {
+            warn!("{:?} was recorded with a different schema, channels may not line up", path);
+        }
+        let duration = records.last().map(Record::t_host).unwrap_or_default();
+        info!("Replaying {} records ({:.1}s) from {:?}", records.len(), duration.as_secs_f32(), path);
+
+        if !app.is_plugin_added::<EguiPlugin>() {
+            app.add_plugins(EguiPlugin);
+        }
+        app
+            .insert_resource(Replay {
+                path,
+                records: Arc::new(records),
+                clock: Arc::new(ReplayClock::new(duration)),
+            })
+            .add_systems(Startup, start_replay)
+            .add_systems(Update, replay_keys)
+            .add_systems(Update, show_timeline)
+        ;
+    }
+}
+
+/// The recording being played back.
+#[derive(Resource)]
+pub struct Replay {
+    path: PathBuf,
+    records: Arc<Vec<Record>>,
+    clock: Arc<ReplayClock>,
+}
+
+/// Where playback is. Shared by the timeline and every replay transport.
+pub struct ReplayClock {
+    duration: Duration,
+    state: Mutex<ClockState>,
+}
+
+struct ClockState {
+    /// Position when `anchor` was taken
+    base: Duration,
+    anchor: Instant,
+    playing: bool,
+    speed: f32,
+    looping: bool,
+    /// Bumped on every seek, so transports know to jump instead of catching up
+    seeks: u64,
+}
+
+impl ReplayClock {
+    fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            state: Mutex::new(ClockState {
+                base: Duration::ZERO,
+                anchor: Instant::now(),
+                playing: true,
+                speed: 1.0,
+                looping: true,
+                seeks: 0,
+            }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ClockState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// The current position, and how many seeks there have been so far.
+    pub fn now(&self) -> (Duration, u64) {
+        let mut state = self.lock();
+        let position = state.position(self.duration);
+        (position, state.seeks)
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.lock().playing
+    }
+
+    /// Pausing keeps the position. Playing from the very end starts over.
+    pub fn set_playing(&self, playing: bool) {
+        let mut state = self.lock();
+        state.rebase(self.duration);
+        if playing && !state.playing && state.base >= self.duration {
+            state.base = Duration::ZERO;
+            state.seeks += 1;
+        }
+        state.playing = playing;
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.lock().speed
+    }
+
+    pub fn set_speed(&self, speed: f32) {
+        let mut state = self.lock();
+        state.rebase(self.duration);
+        state.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+    }
+
+    pub fn is_looping(&self) -> bool {
+        self.lock().looping
+    }
+
+    pub fn set_looping(&self, looping: bool) {
+        let mut state = self.lock();
+        state.rebase(self.duration);
+        state.looping = looping;
+    }
+
+    pub fn seek(&self, position: Duration) {
+        let mut state = self.lock();
+        state.base = position.min(self.duration);
+        state.anchor = Instant::now();
+        state.seeks += 1;
+    }
+}
+
+impl ClockState {
+    /// Wraps around or stops at the end, depending on `looping`.
+    fn position(&mut self, duration: Duration) -> Duration {
+        if !self.playing {
+            return self.base;
+        }
+        let position = self.base + self.anchor.elapsed().mul_f32(self.speed);
+        if position < duration {
+            return position;
+        }
+        if self.looping && !duration.is_zero() {
+            self.base = Duration::from_nanos((position.as_nanos() % duration.as_nanos()) as u64);
+        } else {
+            self.base = duration;
+            self.playing = false;
+        }
+        self.anchor = Instant::now();
+        self.base
+    }
+
+    /// Moves the anchor to now, before the speed or play state changes.
+    fn rebase(&mut self, duration: Duration) {
+        self.base = self.position(duration);
+        self.anchor = Instant::now();
+    }
+}
+
+fn start_replay(runtime: ResMut<TokioTasksRuntime>, queue: Res<SampleQueue>, config: Res<GloveConfig>, schema: Res<GloveSchema>, replay: Res<Replay>) {
+    for device in config.gloves.iter() {
+        if device.source != GloveSource::Replay {
+            continue;
+        }
+        let transport = ReplayTransport {
+            path: replay.path.clone(),
+            hand: device.hand,
+            records: replay.records.clone(),
+            clock: replay.clock.clone(),
+        };
+        spawn_transport(&runtime, &queue, device, &schema, transport);
+    }
+}
+
+/// Plays one hand's part of a recording.
+pub struct ReplayTransport {
+    path: PathBuf,
+    hand: Handedness,
+    records: Arc<Vec<Record>>,
+    clock: Arc<ReplayClock>,
+}
+
+impl ReplayTransport {
+    /// The connection state of this hand as of record `index`.
+    fn connection_at(&self, index: usize) -> GloveConnection {
+        self.records[..index]
+            .iter()
+            .rev()
+            .find_map(|record| match record {
+                Record::Connection { hand, state, .. } if *hand == self.hand => Some(*state),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl GloveTransport for ReplayTransport {
+    fn name(&self) -> String {
+        format!("replay of {}", self.path.display())
+    }
+
+    async fn run(self, mut sink: GloveSink) {
+        let mut ticks = time::interval(REPLAY_TICK);
+        ticks.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+        // Index of the next record to play
+        let mut next = 0;
+        let mut last_position = Duration::ZERO;
+        let mut last_seeks = None;
+
+        loop {
+            ticks.tick().await;
+            let (position, seeks) = self.clock.now();
+
+            // Jump on a seek or when looping back around, rather than playing everything in between
+            if last_seeks != Some(seeks) || position < last_position {
+                last_seeks = Some(seeks);
+                next = self.records.partition_point(|record| record.t_host() < position);
+                sink.set_connection(self.connection_at(next)).await;
+            }
+            last_position = position;
+
+            let mut samples = Vec::new();
+            while let Some(record) = self.records.get(next).filter(|record| record.t_host() <= position) {
+                next += 1;
+                match record {
+                    Record::Sample(sample) if sample.hand == self.hand => {
+                        samples.push(sink.sample(sample.data.clone()));
+                    }
+                    Record::Connection { hand, state, .. } if *hand == self.hand => {
+                        sink.send(std::mem::take(&mut samples)).await;
+                        sink.set_connection(*state).await;
+                    }
+                    _ => {}
+                }
+            }
+            sink.send(samples).await;
+        }
+    }
+}
+
+fn replay_keys(keys: Res<ButtonInput<KeyCode>>, replay: Res<Replay>) {
+    if keys.just_pressed(KeyCode::Space) {
+        replay.clock.set_playing(!replay.clock.is_playing());
+    }
+}
+
+fn show_timeline(mut contexts: EguiContexts, replay: Res<Replay>) {
+    let clock = &replay.clock;
+    let duration = clock.duration().as_secs_f32();
+
+    egui::TopBottomPanel::bottom("replay").show(contexts.ctx_mut(), |ui| {
+        ui.horizontal(|ui| {
+            let playing = clock.is_playing();
+            if ui.button(if playing { "Pause" } else { "Play" }).clicked() {
+                clock.set_playing(!playing);
+            }
+
+            let mut looping = clock.is_looping();
+            if ui.checkbox(&mut looping, "Loop").changed() {
+                clock.set_looping(looping);
+            }
+
+            let mut speed = clock.speed();
+            let speed_slider = egui::Slider::new(&mut speed, MIN_SPEED..=MAX_SPEED)
+                .logarithmic(true)
+                .fixed_decimals(2)
+                .suffix("×");
+            if ui.add(speed_slider).changed() {
+                clock.set_speed(speed);
+            }
+
+            let mut position = clock.now().0.as_secs_f32();
+            ui.label(format!("{:.1} / {:.1}s", position, duration));
+            ui.spacing_mut().slider_width = ui.available_width();
+            let timeline = egui::Slider::new(&mut position, 0.0..=duration).show_value(false);
+            if ui.add(timeline).changed() {
+                clock.seek(Duration::from_secs_f32(position.max(0.0)));
+            }
+        });
+    });
+}
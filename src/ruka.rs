@@ -1,17 +1,34 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
 use bevy::{
-    app::{App, Plugin, Update}, core_pipeline::core_3d::Camera3d, ecs::{
-        component::Component, entity::Entity, query::With, system::{Commands, Query, Res, ResMut, Resource}
+    app::{App, Plugin, Startup, Update}, core::Name, core_pipeline::core_3d::Camera3d, ecs::{
+        change_detection::DetectChanges, component::Component, entity::Entity, query::{Changed, Or, With}, system::{Commands, Query, Res, ResMut, Resource}, world::{Mut, World}
     }, hierarchy::BuildChildren, input::{keyboard::KeyCode, ButtonInput}, math::Vec3, render::color::Color, sprite::Anchor, text::{Text, Text2dBundle, TextSection, TextStyle}, time::Time, transform::components::Transform
 };
+use serde::{Deserialize, Serialize};
+
+use crate::adaptive::RangeTracker;
+use crate::config::GloveConfig;
+use crate::curl::CurlMapping;
+use crate::device::DeviceInfo;
+use crate::diagnostics::{LinkDebugLabel, LinkDiagnostics};
+use crate::filters::{build_chains, FilterChain, SampleClock};
+use crate::schema::{ChannelRole, GloveSchema};
 
 pub struct RukaPlugin;
 
 impl Plugin for RukaPlugin {
     fn build(&self, app: &mut App) {
         app
-            .insert_resource(RukaInput::default())
+            .insert_resource(ActiveHands::default())
+            .add_systems(Startup, spawn_gloves)
+            .add_systems(Startup, spawn_connection_label)
+            .add_systems(Update, switch_active_hands)
+            .add_systems(Update, update_gesture_state)
             .add_systems(Update, toggle_ruka_debug)
             .add_systems(Update, update_ruka_debug)
+            .add_systems(Update, update_connection_label)
             .add_systems(Update, update_ruka_cam)
         ;
 
@@ -19,18 +36,206 @@ impl Plugin for RukaPlugin {
     }
 }
 
-#[derive(Resource, Default)]
+/// Which hand a glove entity is worn on. Every glove entity has exactly one.
+#[derive(Component, Serialize, Deserialize, clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Handedness {
+    Left,
+    Right,
+}
+
+impl Handedness {
+    pub fn other(&self) -> Handedness {
+        match self {
+            Handedness::Left => Handedness::Right,
+            Handedness::Right => Handedness::Left,
+        }
+    }
+}
+
+/// Which glove the debug overlay and the camera are listening to.
+#[derive(Resource)]
+pub struct ActiveHands {
+    pub debug: Handedness,
+    pub camera: Handedness,
+}
+
+impl Default for ActiveHands {
+    fn default() -> Self {
+        Self {
+            debug: Handedness::Right,
+            camera: Handedness::Right,
+        }
+    }
+}
+
+#[derive(Component)]
 pub struct RukaInput {
     init: bool,
 
-    fingers: [u16; 5],
-    finger_limits: [(u16, u16); 5],
+    /// Latest value of every channel in the schema, in schema order, after filtering
+    channels: Vec<f32>,
+    /// The same, as they came from the glove
+    raw: Vec<f32>,
+    /// Filter chain of each channel, if it has one. See filters.rs
+    filters: Vec<Option<FilterChain>>,
+    /// Time between samples, per packet by its first channel
+    clocks: HashMap<usize, SampleClock>,
+    /// Where the flex sensors, accelerometer and gyroscope are in `channels`
+    flex_channels: Vec<usize>,
+    accel_channels: Vec<usize>,
+    gyro_channels: Vec<usize>,
+
+    fingers: Vec<u16>,
+    /// (flexed, straight) reading of each finger
+    finger_limits: Vec<(u16, u16)>,
+    /// Whether `finger_limits` came from a guided calibration rather than being learned as
+    /// readings come in. See calibration.rs
+    calibrated: bool,
+    /// Gyroscope reading at rest, in the glove's own axes
+    gyro_bias: Vec3,
+    axes: AxisMapping,
+    /// How curled a finger has to be to count towards a fist, 0 to 1
+    fist_threshold: f32,
+    /// How each finger's reading becomes curl, from [curl] in glove.toml
+    curl: Vec<CurlMapping>,
+    /// Adaptive range of each finger, if turned on in [adaptive]. Takes over from learning and
+    /// keeps following a calibration. See adaptive.rs
+    trackers: Option<Vec<RangeTracker>>,
+    range_frozen: bool,
+}
 
-    accel: Vec3,
-    gyro: Vec3,
+/// What calibrating a glove found out, as saved in a calibration profile. See profiles.rs
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct GloveCalibration {
+    /// (flexed, straight) reading of each finger
+    pub finger_limits: Vec<(u16, u16)>,
+    pub gyro_bias: [f32; 3],
+    pub fist_threshold: f32,
+    pub axes: AxisMapping,
+}
+
+impl Default for GloveCalibration {
+    fn default() -> Self {
+        Self {
+            finger_limits: Vec::new(),
+            gyro_bias: [0.0; 3],
+            fist_threshold: DEFAULT_FIST_THRESHOLD,
+            axes: AxisMapping::default(),
+        }
+    }
+}
+
+const DEFAULT_FIST_THRESHOLD: f32 = 0.5;
+
+/// Which of the glove's IMU axes is the app's x, y and z, for gloves whose board is mounted
+/// differently. Applies to both the accelerometer and the gyroscope.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AxisMapping {
+    pub x: Axis,
+    pub y: Axis,
+    pub z: Axis,
+}
+
+impl Default for AxisMapping {
+    fn default() -> Self {
+        Self { x: Axis::X, y: Axis::Y, z: Axis::Z }
+    }
+}
+
+impl AxisMapping {
+    pub fn apply(&self, raw: Vec3) -> Vec3 {
+        Vec3::new(self.x.pick(raw), self.y.pick(raw), self.z.pick(raw))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    #[serde(rename = "x")]
+    X,
+    #[serde(rename = "y")]
+    Y,
+    #[serde(rename = "z")]
+    Z,
+    #[serde(rename = "-x")]
+    NegX,
+    #[serde(rename = "-y")]
+    NegY,
+    #[serde(rename = "-z")]
+    NegZ,
+}
+
+impl Axis {
+    fn pick(&self, raw: Vec3) -> f32 {
+        match self {
+            Axis::X => raw.x,
+            Axis::Y => raw.y,
+            Axis::Z => raw.z,
+            Axis::NegX => -raw.x,
+            Axis::NegY => -raw.y,
+            Axis::NegZ => -raw.z,
+        }
+    }
+}
+
+/// Where the glove connection currently is in its lifecycle. Updated by the BLE task.
+#[derive(Component, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GloveConnection {
+    #[default]
+    Idle,
+    Scanning,
+    Connecting,
+    DiscoveringServices,
+    Streaming,
+    Disconnected,
+}
+
+/// The gesture a glove is currently making, updated once per frame.
+#[derive(Component, Default)]
+pub struct GestureState {
+    pub current: RukaGesture,
+}
+
+/// Finds the entity of the glove worn on the given hand.
+pub fn glove_entity(world: &mut World, hand: Handedness) -> Option<Entity> {
+    let mut gloves = world.query::<(Entity, &Handedness)>();
+    gloves
+        .iter(world)
+        .find(|(_, glove_hand)| **glove_hand == hand)
+        .map(|(entity, _)| entity)
+}
+
+/// Mutable access to a component of the glove worn on the given hand.
+pub fn glove_mut<C: Component>(world: &mut World, hand: Handedness) -> Option<Mut<'_, C>> {
+    let entity = glove_entity(world, hand)?;
+    world.get_mut::<C>(entity)
 }
 
 impl RukaInput {
+    pub fn new(schema: &GloveSchema, config: &GloveConfig) -> Self {
+        let flex_channels = schema.role_channels(ChannelRole::Flex);
+        Self {
+            curl: config.curl.mappings(flex_channels.len()),
+            trackers: config.adaptive.enabled.then(|| vec![RangeTracker::new(&config.adaptive); flex_channels.len()]),
+            range_frozen: false,
+            init: false,
+            channels: vec![0.0; schema.channel_count()],
+            raw: vec![0.0; schema.channel_count()],
+            filters: build_chains(schema, &config.filters),
+            clocks: HashMap::new(),
+            fingers: vec![0; flex_channels.len()],
+            finger_limits: vec![(0, 0); flex_channels.len()],
+            calibrated: false,
+            gyro_bias: Vec3::ZERO,
+            axes: AxisMapping::default(),
+            fist_threshold: DEFAULT_FIST_THRESHOLD,
+            flex_channels,
+            accel_channels: schema.role_channels(ChannelRole::Accel),
+            gyro_channels: schema.role_channels(ChannelRole::Gyro),
+        }
+    }
+
     pub fn is_init(&self) -> bool {
         self.init
     }
@@ -39,15 +244,130 @@ impl RukaInput {
         self.init = init;
     }
 
-    pub fn get_fingers_float(&self) -> [f32; 5] {
-        let mut fingers = [0.0; 5];
-        for (i, finger) in self.fingers.iter().enumerate() {
-            fingers[i] = *finger as f32 / 16384.0;
+    pub fn finger_count(&self) -> usize {
+        self.fingers.len()
+    }
+
+    /// Uses calibrated ranges from now on instead of learning them.
+    pub fn set_finger_limits(&mut self, limits: Vec<(u16, u16)>) {
+        if limits.len() != self.finger_limits.len() {
+            return;
+        }
+        self.finger_limits = limits;
+        self.calibrated = true;
+        if let Some(trackers) = self.trackers.as_mut() {
+            for (tracker, (flexed, straight)) in trackers.iter_mut().zip(self.finger_limits.iter()) {
+                tracker.seed(*flexed as f32, *straight as f32);
+            }
+        }
+    }
+
+    pub fn is_adaptive(&self) -> bool {
+        self.trackers.is_some()
+    }
+
+    pub fn is_range_frozen(&self) -> bool {
+        self.range_frozen
+    }
+
+    /// Stops or restarts adapting the finger ranges.
+    pub fn set_range_frozen(&mut self, frozen: bool) {
+        self.range_frozen = frozen;
+    }
+
+    pub fn set_gyro_bias(&mut self, bias: Vec3) {
+        self.gyro_bias = bias;
+    }
+
+    /// Forgets the calibration and goes back to learning the ranges.
+    pub fn reset_calibration(&mut self) {
+        self.apply_calibration(&GloveCalibration::default());
+    }
+
+    /// The calibration in use, if there is one.
+    pub fn calibration(&self) -> Option<GloveCalibration> {
+        self.calibrated.then(|| GloveCalibration {
+            finger_limits: self.finger_limits.clone(),
+            gyro_bias: self.gyro_bias.to_array(),
+            fist_threshold: self.fist_threshold,
+            axes: self.axes,
+        })
+    }
+
+    /// Switches to a saved calibration. Finger ranges for a different number of fingers
+    /// are ignored, and learned instead.
+    pub fn apply_calibration(&mut self, calibration: &GloveCalibration) {
+        self.finger_limits = vec![(0, 0); self.fingers.len()];
+        self.calibrated = false;
+        if let Some(trackers) = self.trackers.as_mut() {
+            for tracker in trackers.iter_mut() {
+                tracker.reset();
+            }
         }
-        fingers
+        self.set_finger_limits(calibration.finger_limits.clone());
+        self.gyro_bias = Vec3::from_array(calibration.gyro_bias);
+        self.axes = calibration.axes;
+        self.fist_threshold = calibration.fist_threshold;
     }
 
-    pub fn update_fingers(&mut self, new_fingers: [u16; 5]) {
+    /// How curled each finger is, from 0 (straight) to 1 (flexed), thumb first.
+    pub fn get_curl(&self) -> Vec<f32> {
+        self.fingers
+            .iter()
+            .zip(self.finger_limits.iter())
+            .zip(self.curl.iter())
+            .map(|((finger, limits), curl)| curl.apply(*finger as f32, *limits))
+            .collect()
+    }
+
+    /// Stores a decoded packet whose first channel is at `offset` in the schema, and runs it
+    /// through the channels' filters. Called for every sample, with the times it was stamped with.
+    pub fn update_channels(&mut self, offset: usize, values: &[f32], t_host: Instant, t_device: Option<Duration>) {
+        let range = offset..offset + values.len();
+        let Some(raw) = self.raw.get_mut(range.clone()) else {
+            return;
+        };
+        raw.copy_from_slice(values);
+
+        let dt = self.clocks.entry(offset).or_default().tick(t_host, t_device);
+        for i in range.clone() {
+            self.channels[i] = match self.filters.get_mut(i) {
+                Some(Some(chain)) => chain.apply(self.raw[i], dt),
+                _ => self.raw[i],
+            };
+        }
+
+        if self.flex_channels.iter().any(|i| range.contains(i)) {
+            let new_fingers = self.flex_channels.iter().map(|i| self.channels[*i] as u16).collect();
+            self.update_fingers(new_fingers);
+        }
+    }
+
+    fn update_fingers(&mut self, new_fingers: Vec<u16>) {
+        if let Some(trackers) = self.trackers.as_mut() {
+            if !self.range_frozen {
+                let now = Instant::now();
+                for (i, finger) in new_fingers.iter().enumerate() {
+                    let Some((low, high)) = trackers[i].update(*finger as f32, now) else {
+                        continue;
+                    };
+                    // Keep a calibration's way round
+                    let (flexed, straight) = self.finger_limits[i];
+                    self.finger_limits[i] = match flexed > straight {
+                        true => (high as u16, low as u16),
+                        false => (low as u16, high as u16),
+                    };
+                }
+            }
+            self.fingers = new_fingers;
+            return;
+        }
+
+        if self.calibrated {
+            self.fingers = new_fingers;
+            return;
+        }
+
         for (i, finger) in new_fingers.iter().enumerate() {
             if self.finger_limits[i].0 < 100 {
                 self.finger_limits[i].0 = 14000;
@@ -60,61 +380,44 @@ impl RukaInput {
             }
         }
 
-        println!("Limits: {:?}", self.finger_limits);
         self.fingers = new_fingers;
     }
 
-    pub fn update_imu(&mut self, new_imu: [f32; 6]) {
-        self.accel = Vec3::new(new_imu[0], new_imu[1], new_imu[2]);
-        self.gyro = Vec3::new(new_imu[3], new_imu[4], new_imu[5]);
+    fn vec3(&self, indices: &[usize]) -> Vec3 {
+        match indices {
+            [x, y, z] => Vec3::new(self.channels[*x], self.channels[*y], self.channels[*z]),
+            _ => Vec3::ZERO,
+        }
     }
 
     pub fn get_gyro(&self) -> Vec3 {
-        self.gyro
+        self.axes.apply(self.vec3(&self.gyro_channels) - self.gyro_bias)
     }
 
     pub fn get_accel(&self) -> Vec3 {
-        self.accel
-    }
-
-    pub fn get_all_for_debug(&self) -> [f32; 12] {
-        let all = [
-            self.fingers[0] as f32,
-            self.fingers[1] as f32,
-            self.fingers[2] as f32,
-            self.fingers[3] as f32,
-            self.fingers[4] as f32,
-            self.accel.x,
-            self.accel.y,
-            self.accel.z,
-            self.gyro.x,
-            self.gyro.y,
-            self.gyro.z,
-            self.get_gesture().to_float()
-        ];
-        all
+        self.axes.apply(self.vec3(&self.accel_channels))
     }
 
-    pub fn get_gesture(&self) -> RukaGesture {
-        // Naive implementation of checking whether the hand is making a fist
-        
-        let mut is_fist: bool = true;
-
-        for i in 0..self.fingers.len(){
-            // Threshold for making a fist is 0.3 along the limit for that finger
-            let from_straight = self.fingers[i].abs_diff(self.finger_limits[i].1);
-            let from_flexed = self.fingers[i].abs_diff(self.finger_limits[i].0);
-
-            let deny_fist = from_straight as f32 <= from_flexed as f32;
-            is_fist = !deny_fist;
-
-            println!("Finger {}: | ll{} c{} ul{} | s{} f{}, denying fist: {}", 
-                i, 
-                self.finger_limits[i].0, self.fingers[i], self.finger_limits[i].1, 
-                from_straight, from_flexed, deny_fist);
+    /// Schema channel `i` as it came from the glove, if it is filtered.
+    pub fn get_raw(&self, i: usize) -> Option<f32> {
+        match self.filters.get(i) {
+            Some(Some(_)) => self.raw.get(i).copied(),
+            _ => None,
         }
+    }
 
-        if !is_fist {
+    /// Every schema channel, then the curl of every finger, then the current gesture.
+    pub fn get_all_for_debug(&self) -> Vec<f32> {
+        let mut all = self.channels.clone();
+        all.extend(self.get_curl());
+        all.push(self.get_gesture().to_float());
+        all
+    }
+
+    /// A fist once every finger is curled past `fist_threshold`.
+    pub fn get_gesture(&self) -> RukaGesture {
+        let curl = self.get_curl();
+        if !curl.is_empty() && curl.iter().all(|curl| *curl >= self.fist_threshold) {
             RukaGesture::Fist
         } else {
             RukaGesture::Idle
@@ -122,8 +425,9 @@ impl RukaInput {
     }
 }
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
 pub enum RukaGesture {
+    #[default]
     Idle,
     Fist, 
     ThumbsUp,
@@ -164,48 +468,97 @@ impl RukaGesture {
     }
 }
 
+pub fn spawn_gloves(
+    mut commands: Commands,
+    config: Res<GloveConfig>,
+    schema: Res<GloveSchema>,
+    mut hands: ResMut<ActiveHands>,
+) {
+    for device in config.gloves.iter() {
+        commands.spawn((
+            Name::new(format!("{:?} glove", device.hand)),
+            device.hand,
+            RukaInput::new(&schema, &config),
+            GloveConnection::default(),
+            DeviceInfo::default(),
+            LinkDiagnostics::new(device.hand, &schema),
+            GestureState::default(),
+        ));
+    }
+
+    // Listen to the first configured glove until told otherwise
+    if let Some(first) = config.gloves.first() {
+        hands.debug = first.hand;
+        hands.camera = first.hand;
+    }
+}
+
+fn switch_active_hands(
+    keys: Res<ButtonInput<KeyCode>>,
+    gloves: Query<&Handedness>,
+    mut hands: ResMut<ActiveHands>,
+) {
+    let has_glove = |hand: Handedness| gloves.iter().any(|glove| *glove == hand);
+
+    if keys.just_pressed(KeyCode::KeyH) && has_glove(hands.debug.other()) {
+        hands.debug = hands.debug.other();
+    }
+    if keys.just_pressed(KeyCode::KeyC) && has_glove(hands.camera.other()) {
+        hands.camera = hands.camera.other();
+    }
+}
+
+fn update_gesture_state(
+    mut gloves: Query<(&RukaInput, &mut GestureState)>,
+) {
+    for (ruka, mut gesture) in gloves.iter_mut() {
+        let current = ruka.get_gesture();
+        if gesture.current != current {
+            gesture.current = current;
+        }
+    }
+}
+
+/// One line of the debug overlay, showing the value at this index of `get_all_for_debug`.
 #[derive(Component)]
-struct RukaDebugLabel;
+struct RukaDebugLabel(usize);
 
 #[derive(Component)]
 struct RukaDebugFinger;
 
 fn toggle_ruka_debug(
     mut commands: Commands, 
-    ruka: Res<RukaInput>,
+    gloves: Query<(&Handedness, &RukaInput, &LinkDiagnostics)>,
+    hands: Res<ActiveHands>,
     keys: Res<ButtonInput<KeyCode>>,
-    labels: Query<Entity, With<RukaDebugLabel>>,
+    labels: Query<Entity, Or<(With<RukaDebugLabel>, With<LinkDebugLabel>)>>,
 ) {
     if !keys.just_pressed(KeyCode::KeyR) {
         return;
     }
 
     if labels.iter().count() == 0 {
+        let Some((_, ruka, link)) = gloves.iter().find(|(hand, _, _)| **hand == hands.debug) else {
+            return;
+        };
+
         let mut i = 0;
         for lbl in ruka.get_all_for_debug().iter(){
             commands.spawn((
-                Text2dBundle {
-                    text: Text {
-                        sections: vec![TextSection {
-                            value: format!("{}: {}", i + 1, lbl),
-                            style: TextStyle {
-                                font_size: 20.0,
-                                color: Color::WHITE,
-                               ..Default::default()
-                            },
-                           ..Default::default()
-                        }],
-                        ..Default::default()
-                    },
-                    text_anchor: Anchor::TopLeft,
-                    transform: Transform::from_xyz(20.0, 20.0 + i as f32 * 20.0, 1000.0),
-                    ..Default::default()
-                },
-                RukaDebugLabel,
+                debug_text(format!("{:.2}", lbl), i),
+                RukaDebugLabel(i),
             ));
 
             i += 1;
         }
+
+        // Link diagnostics go below the values
+        for j in 0..link.diagnostics().len() {
+            commands.spawn((
+                debug_text(String::new(), i + j),
+                LinkDebugLabel(j),
+            ));
+        }
     }
 
     else {
@@ -213,28 +566,137 @@ fn toggle_ruka_debug(
     }
 }
 
-fn update_ruka_debug(
-    ruka: Res<RukaInput>,
-    mut labels: Query<&mut Text, With<RukaDebugLabel>>,
+/// A line of the debug overlay, `row` lines down from the top.
+fn debug_text(value: String, row: usize) -> Text2dBundle {
+    Text2dBundle {
+        text: Text {
+            sections: vec![TextSection {
+                value,
+                style: TextStyle {
+                    font_size: 20.0,
+                    color: Color::WHITE,
+                   ..Default::default()
+                },
+               ..Default::default()
+            }],
+            ..Default::default()
+        },
+        text_anchor: Anchor::TopLeft,
+        transform: Transform::from_xyz(20.0, 20.0 + row as f32 * 20.0, 1000.0),
+        ..Default::default()
+    }
+}
+
+#[derive(Component)]
+struct RukaConnectionLabel;
+
+fn spawn_connection_label(
+    mut commands: Commands,
 ) {
-    let mut i = 0;
-    let fist: bool = ruka.get_gesture() == RukaGesture::Fist;
+    commands.spawn((
+        Text2dBundle {
+            text: Text::default(),
+            text_anchor: Anchor::TopLeft,
+            transform: Transform::from_xyz(20.0, 0.0, 1000.0),
+            ..Default::default()
+        },
+        RukaConnectionLabel,
+    ));
+}
+
+fn update_connection_label(
+    gloves: Query<(&Handedness, &GloveConnection, &DeviceInfo)>,
+    changed: Query<(), Or<(Changed<GloveConnection>, Changed<DeviceInfo>)>>,
+    hands: Res<ActiveHands>,
+    config: Res<GloveConfig>,
+    mut labels: Query<&mut Text, With<RukaConnectionLabel>>,
+) {
+    if changed.is_empty() && !hands.is_changed() {
+        return;
+    }
+
+    let mut gloves: Vec<(&Handedness, &GloveConnection, &DeviceInfo)> = gloves.iter().collect();
+    gloves.sort_by_key(|(hand, _, _)| **hand as u8);
+
+    let sections: Vec<TextSection> = gloves
+        .into_iter()
+        .map(|(hand, connection, info)| {
+            let mut value = format!("{:?} glove: {:?}", hand, connection);
+            let summary = info.summary();
+            if !summary.is_empty() {
+                value.push_str(&format!(" ({})", summary));
+            }
+            if *hand == hands.debug {
+                value.push_str(" [debug]");
+            }
+            if *hand == hands.camera {
+                value.push_str(" [camera]");
+            }
+            value.push('\n');
+
+            TextSection {
+                value,
+                style: TextStyle {
+                    font_size: 20.0,
+                    color: match connection {
+                        GloveConnection::Streaming if info.is_low(config.low_battery) => Color::YELLOW,
+                        GloveConnection::Streaming => Color::GREEN,
+                        GloveConnection::Disconnected => Color::RED,
+                        _ => Color::WHITE,
+                    },
+                    ..Default::default()
+                },
+            }
+        })
+        .collect();
+
     for mut lbl in labels.iter_mut() {
-        lbl.sections[0].value = format!("{:.2}", ruka.get_all_for_debug()[i]);
+        lbl.sections = sections.clone();
+    }
+}
+
+fn update_ruka_debug(
+    gloves: Query<(&Handedness, &RukaInput, &GestureState)>,
+    hands: Res<ActiveHands>,
+    schema: Res<GloveSchema>,
+    mut labels: Query<(&mut Text, &RukaDebugLabel)>,
+) {
+    let Some((_, ruka, gesture)) = gloves.iter().find(|(hand, _, _)| **hand == hands.debug) else {
+        return;
+    };
+
+    let values = ruka.get_all_for_debug();
+    let channels: Vec<_> = schema.channels().collect();
+    let fingers = ruka.finger_count();
+    let fist: bool = gesture.current == RukaGesture::Fist;
+    for (mut lbl, RukaDebugLabel(i)) in labels.iter_mut() {
+        let Some(value) = values.get(*i) else {
+            continue;
+        };
+        lbl.sections[0].value = match (channels.get(*i), ruka.get_raw(*i)) {
+            (Some(channel), Some(raw)) => format!("{}: {:.2} -> {:.2} {}", channel.name, raw, value, channel.units),
+            (Some(channel), None) => format!("{}: {:.2} {}", channel.name, value, channel.units),
+            (None, _) if *i < channels.len() + fingers => format!("curl {}: {:.2}", i - channels.len(), value),
+            (None, _) => format!("gesture: {}", gesture.current.to_string()),
+        };
         lbl.sections[0].style.color = match fist {
             true => Color::GREEN,
             false => Color::WHITE
         };
-        i += 1;
     }
 }
 
 fn update_ruka_cam(
-    ruka: Res<RukaInput>,
+    gloves: Query<(&Handedness, &RukaInput, &GloveConnection, &GestureState)>,
+    hands: Res<ActiveHands>,
     time: Res<Time>,
     mut cam: Query<&mut Transform, With<Camera3d>>,
 ){
-    if ruka.get_gesture() != RukaGesture::Fist {
+    let Some((_, ruka, connection, gesture)) = gloves.iter().find(|(hand, _, _, _)| **hand == hands.camera) else {
+        return;
+    };
+
+    if *connection != GloveConnection::Streaming || gesture.current != RukaGesture::Fist {
         return;
     }
 
@@ -249,3 +711,60 @@ fn update_ruka_cam(
     // cam_transform.translation.z += ruka.get_accel().y * time.delta_seconds() * 0.01;
     // cam_transform.translation.y += (ruka.get_accel().z + 9.7) * time.delta_seconds() * 0.01;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adaptive::AdaptiveConfig;
+
+    /// A glove on the built-in schema with fingers reading 1000 flexed and 10000 straight.
+    fn calibrated_glove() -> RukaInput {
+        let mut ruka = RukaInput::new(&GloveSchema::default(), &GloveConfig::default());
+        ruka.set_finger_limits(vec![(1000, 10000); ruka.finger_count()]);
+        ruka
+    }
+
+    fn gesture(ruka: &mut RukaInput, fingers: [f32; 5]) -> RukaGesture {
+        ruka.update_channels(0, &fingers, Instant::now(), None);
+        ruka.get_gesture()
+    }
+
+    #[test]
+    fn fist_needs_every_finger_curled() {
+        let mut ruka = calibrated_glove();
+        assert_eq!(gesture(&mut ruka, [10000.0; 5]), RukaGesture::Idle);
+        assert_eq!(gesture(&mut ruka, [1000.0; 5]), RukaGesture::Fist);
+        // Pointing is not a fist, whichever finger is left out
+        assert_eq!(gesture(&mut ruka, [1000.0, 10000.0, 1000.0, 1000.0, 1000.0]), RukaGesture::Idle);
+        assert_eq!(gesture(&mut ruka, [1000.0, 1000.0, 1000.0, 1000.0, 10000.0]), RukaGesture::Idle);
+    }
+
+    #[test]
+    fn frozen_ranges_stay_put() {
+        let config = GloveConfig {
+            adaptive: AdaptiveConfig { enabled: true, ..Default::default() },
+            ..Default::default()
+        };
+        let mut ruka = RukaInput::new(&GloveSchema::default(), &config);
+        ruka.update_channels(0, &[1000.0; 5], Instant::now(), None);
+        let limits = ruka.finger_limits.clone();
+
+        ruka.set_range_frozen(true);
+        for _ in 0..200 {
+            ruka.update_channels(0, &[5000.0; 5], Instant::now(), None);
+        }
+        assert_eq!(ruka.finger_limits, limits);
+
+        ruka.set_range_frozen(false);
+        for _ in 0..200 {
+            ruka.update_channels(0, &[5000.0; 5], Instant::now(), None);
+        }
+        assert_eq!(ruka.finger_limits, vec![(1000, 5000); 5]);
+    }
+
+    #[test]
+    fn no_fist_before_anything_is_known() {
+        let ruka = RukaInput::new(&GloveSchema::default(), &GloveConfig::default());
+        assert_eq!(ruka.get_gesture(), RukaGesture::Idle);
+    }
+}
@@ -0,0 +1,357 @@
+// Declarative description of what the glove sends, loaded from schema.toml.
+//
+// Every payload the glove sends is a packet: a fixed run of fields, each one channel.
+// Decoding and the debug overlay go by the schema, so a new sensor on the glove
+// only needs a new entry in the file. See schema.toml for the format.
+
+use std::f32::consts::TAU;
+use std::path::Path;
+
+use bevy::ecs::system::Resource;
+use btleplug::api::bleuuid::uuid_from_u16;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::command::Command;
+use crate::protocol::{ByteOrder, DecodeError, FieldType, SampleData, BYTE_ORDER, IMU_SCALE, KIND_FLEX, KIND_IMU};
+
+#[derive(Resource, Deserialize, Debug, Clone)]
+pub struct GloveSchema {
+    pub packets: Vec<PacketSchema>,
+    #[serde(default)]
+    pub commands: CommandSchema,
+}
+
+/// Writable characteristics for host to glove commands, see command.rs.
+/// Commands without a characteristic are reported as failed.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct CommandSchema {
+    pub vibrate: Option<Uuid>,
+    pub led: Option<Uuid>,
+    pub imu_range: Option<Uuid>,
+    pub sample_rate: Option<Uuid>,
+}
+
+impl CommandSchema {
+    pub fn uuid(&self, command: &Command) -> Option<Uuid> {
+        match command {
+            Command::Vibrate { .. } => self.vibrate,
+            Command::SetLed { .. } => self.led,
+            Command::SetImuRange { .. } => self.imu_range,
+            Command::SetSampleRate(_) => self.sample_rate,
+        }
+    }
+}
+
+/// One payload the glove sends.
+#[derive(Deserialize, Debug, Clone)]
+pub struct PacketSchema {
+    pub name: String,
+    /// Type byte in serial and UDP frames, and the HID report ID.
+    pub id: u8,
+    /// BLE characteristic the packet arrives on.
+    #[serde(default)]
+    pub uuid: Option<Uuid>,
+    /// What starts the packet's lines in the serial line protocol, e.g. "F".
+    #[serde(default)]
+    pub tag: Option<String>,
+    #[serde(default)]
+    pub byte_order: ByteOrder,
+    pub channels: Vec<ChannelGroup>,
+}
+
+/// A run of channels with the same type and units, e.g. the three accelerometer axes.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ChannelGroup {
+    pub names: Vec<String>,
+    #[serde(rename = "type")]
+    pub field_type: FieldType,
+    /// Raw values are multiplied by this to get real units.
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+    #[serde(default)]
+    pub units: String,
+    /// What the app uses these channels for. Channels without a role are only shown and recorded.
+    #[serde(default)]
+    pub role: Option<ChannelRole>,
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelRole {
+    /// One flex sensor per channel, thumb first
+    Flex,
+    /// Accelerometer x, y, z
+    Accel,
+    /// Gyroscope x, y, z
+    Gyro,
+    /// The glove's own clock in milliseconds, at most one per packet
+    Timestamp,
+}
+
+/// A channel as seen by the rest of the app.
+pub struct ChannelInfo<'a> {
+    pub name: &'a str,
+    pub units: &'a str,
+    pub role: Option<ChannelRole>,
+}
+
+impl Default for GloveSchema {
+    /// The current glove: five flex sensors and a six axis IMU.
+    fn default() -> Self {
+        Self {
+            packets: vec![
+                PacketSchema {
+                    name: String::from("flex"),
+                    id: KIND_FLEX,
+                    uuid: Some(uuid_from_u16(0x2AF9)),
+                    tag: Some(String::from("F")),
+                    byte_order: BYTE_ORDER,
+                    channels: vec![ChannelGroup {
+                        names: ["thumb", "index", "middle", "ring", "pinky"].map(String::from).to_vec(),
+                        field_type: FieldType::U16,
+                        scale: 1.0,
+                        units: String::from("counts"),
+                        role: Some(ChannelRole::Flex),
+                    }],
+                },
+                PacketSchema {
+                    name: String::from("imu"),
+                    id: KIND_IMU,
+                    uuid: Some(uuid_from_u16(0x2713)),
+                    tag: Some(String::from("I")),
+                    byte_order: BYTE_ORDER,
+                    channels: vec![
+                        ChannelGroup {
+                            names: ["accel_x", "accel_y", "accel_z"].map(String::from).to_vec(),
+                            field_type: FieldType::I16,
+                            scale: 1.0 / IMU_SCALE,
+                            units: String::from("m/s²"),
+                            role: Some(ChannelRole::Accel),
+                        },
+                        ChannelGroup {
+                            names: ["gyro_x", "gyro_y", "gyro_z"].map(String::from).to_vec(),
+                            field_type: FieldType::I16,
+                            scale: 1.0 / IMU_SCALE,
+                            units: String::from("°/s"),
+                            role: Some(ChannelRole::Gyro),
+                        },
+                    ],
+                },
+            ],
+            commands: CommandSchema::default(),
+        }
+    }
+}
+
+impl GloveSchema {
+    /// Reads the schema file. A missing file means the built-in schema for the current glove,
+    /// a broken one is reported and also falls back to it.
+    pub fn load(path: &Path) -> Self {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(_) => {
+                println!("No schema found at {:?}, using the built-in one", path);
+                return GloveSchema::default();
+            }
+        };
+
+        let schema = match toml::from_str::<GloveSchema>(&contents) {
+            Ok(schema) => schema,
+            Err(err) => {
+                eprintln!("Error parsing {:?}, using the built-in schema: {}", path, err);
+                return GloveSchema::default();
+            }
+        };
+        if let Err(err) = schema.validate() {
+            eprintln!("Invalid schema {:?}, using the built-in one: {}", path, err);
+            return GloveSchema::default();
+        }
+        schema
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.packets.is_empty() {
+            return Err(String::from("no packets"));
+        }
+        for (i, packet) in self.packets.iter().enumerate() {
+            if packet.channels.iter().all(|group| group.names.is_empty()) {
+                return Err(format!("packet {} has no channels", packet.name));
+            }
+            for other in self.packets[..i].iter() {
+                if other.id == packet.id {
+                    return Err(format!("packets {} and {} share id {}", other.name, packet.name, packet.id));
+                }
+                if packet.uuid.is_some() && other.uuid == packet.uuid {
+                    return Err(format!("packets {} and {} share a uuid", other.name, packet.name));
+                }
+            }
+        }
+        for packet in self.packets.iter() {
+            if packet.channels().filter(|channel| channel.role == Some(ChannelRole::Timestamp)).count() > 1 {
+                return Err(format!("packet {} has more than one timestamp channel", packet.name));
+            }
+        }
+        for role in [ChannelRole::Accel, ChannelRole::Gyro] {
+            let count = self.channels().filter(|channel| channel.role == Some(role)).count();
+            if count != 0 && count != 3 {
+                return Err(format!("{:?} needs exactly 3 channels, found {}", role, count));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn by_id(&self, id: u8) -> Option<&PacketSchema> {
+        self.packets.iter().find(|packet| packet.id == id)
+    }
+
+    pub fn by_uuid(&self, uuid: Uuid) -> Option<&PacketSchema> {
+        self.packets.iter().find(|packet| packet.uuid == Some(uuid))
+    }
+
+    pub fn by_tag(&self, tag: &str) -> Option<&PacketSchema> {
+        self.packets.iter().find(|packet| packet.tag.as_deref() == Some(tag))
+    }
+
+    pub fn decode(&self, id: u8, data: &[u8]) -> Result<SampleData, DecodeError> {
+        self.by_id(id).ok_or(DecodeError::UnknownPacket(id))?.decode(data)
+    }
+
+    /// Every channel of every packet, in order. This is the order `RukaInput` keeps values in.
+    pub fn channels(&self) -> impl Iterator<Item = ChannelInfo<'_>> {
+        self.packets.iter().flat_map(|packet| packet.channels())
+    }
+
+    pub fn channel_count(&self) -> usize {
+        self.packets.iter().map(PacketSchema::channel_count).sum()
+    }
+
+    /// Index of a packet's first channel among all channels.
+    pub fn channel_offset(&self, id: u8) -> Option<usize> {
+        let mut offset = 0;
+        for packet in self.packets.iter() {
+            if packet.id == id {
+                return Some(offset);
+            }
+            offset += packet.channel_count();
+        }
+        None
+    }
+
+    /// Indices of all channels with the given role.
+    pub fn role_channels(&self, role: ChannelRole) -> Vec<usize> {
+        self.channels()
+            .enumerate()
+            .filter(|(_, channel)| channel.role == Some(role))
+            .map(|(i, _)| i)
+            .collect()
+    }
+}
+
+impl PacketSchema {
+    pub fn channels(&self) -> impl Iterator<Item = ChannelInfo<'_>> {
+        self.channels.iter().flat_map(|group| {
+            group.names.iter().map(move |name| ChannelInfo {
+                name,
+                units: &group.units,
+                role: group.role,
+            })
+        })
+    }
+
+    pub fn channel_count(&self) -> usize {
+        self.channels.iter().map(|group| group.names.len()).sum()
+    }
+
+    /// The values of this packet's channels with the given role, in order.
+    pub fn role_values(&self, values: &[f32], role: ChannelRole) -> Vec<f32> {
+        self.channels()
+            .zip(values)
+            .filter(|(channel, _)| channel.role == Some(role))
+            .map(|(_, value)| *value)
+            .collect()
+    }
+
+    /// Length of the payload in bytes.
+    pub fn payload_len(&self) -> usize {
+        self.channels.iter().map(|group| group.names.len() * group.field_type.size()).sum()
+    }
+
+    pub fn decode(&self, data: &[u8]) -> Result<SampleData, DecodeError> {
+        if data.len() != self.payload_len() {
+            return Err(DecodeError::WrongLength { packet: self.id, expected: self.payload_len(), actual: data.len() });
+        }
+
+        let mut values = Vec::with_capacity(self.channel_count());
+        let mut timestamp = None;
+        let mut fields = data;
+        for group in self.channels.iter() {
+            let size = group.field_type.size();
+            for _ in group.names.iter() {
+                let (field, rest) = fields.split_at(size);
+                let raw = group.field_type.read(field, self.byte_order);
+                values.push(raw as f32 * group.scale);
+                if group.role == Some(ChannelRole::Timestamp) {
+                    timestamp = Some(raw * group.scale as f64);
+                }
+                fields = rest;
+            }
+        }
+
+        Ok(SampleData {
+            packet: self.id,
+            values,
+            timestamp,
+            raw: data.to_vec(),
+        })
+    }
+
+    /// Plausible values for a simulated glove `seconds` into a session: the hand slowly opens
+    /// and closes while tilting back and forth.
+    pub fn synthetic_values(&self, seconds: f32) -> Vec<f32> {
+        // One full open-close cycle every four seconds or so
+        let phase = (seconds / 4.0 * TAU).sin();
+
+        let mut accel = [0.0, 0.0, 9.81].into_iter();
+        let mut gyro = [phase * 45.0, 0.0, 0.0].into_iter();
+        self.channels()
+            .map(|channel| match channel.role {
+                Some(ChannelRole::Flex) => 15000.0 + 1500.0 * phase,
+                Some(ChannelRole::Accel) => accel.next().unwrap_or(0.0),
+                Some(ChannelRole::Gyro) => gyro.next().unwrap_or(0.0),
+                Some(ChannelRole::Timestamp) => seconds * 1000.0,
+                None => 0.0,
+            })
+            .collect()
+    }
+
+    /// Builds the payload the glove would send for these values. Missing values are sent as 0.
+    pub fn encode(&self, values: &[f32]) -> Vec<u8> {
+        self.encode_fields(values, None)
+    }
+
+    /// Builds the payload a sample was decoded from, timestamp and all.
+    pub fn encode_sample(&self, data: &SampleData) -> Vec<u8> {
+        self.encode_fields(&data.values, data.timestamp)
+    }
+
+    fn encode_fields(&self, values: &[f32], timestamp: Option<f64>) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.payload_len());
+        let mut values = values.iter();
+        for group in self.channels.iter() {
+            for _ in group.names.iter() {
+                let value = values.next().copied().unwrap_or(0.0);
+                let raw = match (group.role, timestamp) {
+                    (Some(ChannelRole::Timestamp), Some(timestamp)) => timestamp / group.scale as f64,
+                    _ => (value / group.scale) as f64,
+                };
+                group.field_type.write(raw, self.byte_order, &mut data);
+            }
+        }
+        data
+    }
+}
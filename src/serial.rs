@@ -0,0 +1,574 @@
+// Serial (USB CDC) glove source for the companion Arduino firmware.
+//
+// Two framings are supported:
+//  - binary: 0xA5 0x5A, type, payload length, payload, XOR of type/length/payload.
+//    The type is the schema packet id, and the payload the same bytes as over BLE.
+//  - line: "<tag>,<values>*CS", NMEA style, e.g. "F,<5 flex counts>*CS" and "I,<ax,ay,az,gx,gy,gz>*CS".
+//    The tag is the schema packet's `tag` and the values are already in real units. CS is the
+//    XOR of every character before the '*' in hex. Any other line is firmware chatter and ignored.
+//
+// Both resync on garbage, so the port can be opened mid-stream. On Linux a pty pair
+// (e.g. `socat -d -d pty,raw,echo=0 pty,raw,echo=0`) stands in for the glove.
+
+use std::fmt;
+use std::io::{self, Read};
+use std::time::Duration;
+
+use bevy::app::{App, Plugin, Startup};
+use bevy::ecs::system::{Res, ResMut};
+use bevy::log::{debug, info, warn};
+use serialport::SerialPort;
+use tokio::sync::mpsc;
+use tokio::{task, time};
+
+use crate::asyncs::TokioTasksRuntime;
+use crate::config::{GloveConfig, GloveSource, SerialConfig, SerialProtocol};
+use crate::protocol::{DecodeError, SampleData};
+use crate::ruka::GloveConnection;
+use crate::schema::{ChannelRole, GloveSchema};
+use crate::transport::{spawn_transport, GloveSink, GloveTransport, SampleQueue};
+
+const SYNC: [u8; 2] = [0xA5, 0x5A];
+/// Anything longer than this can't be one of our frames, so the sync word was a false match.
+const MAX_PAYLOAD: usize = 32;
+/// Longest line we wait for before deciding the newline got lost.
+const MAX_LINE: usize = 256;
+
+/// How long to wait before trying to open the port again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(2);
+/// How long a blocking read waits before checking whether anyone is still listening.
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+pub struct SerialPlugin;
+
+impl Plugin for SerialPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Startup, connect_serial)
+        ;
+    }
+}
+
+fn connect_serial(runtime: ResMut<TokioTasksRuntime>, queue: Res<SampleQueue>, config: Res<GloveConfig>, schema: Res<GloveSchema>) {
+    for device in config.gloves.iter() {
+        if device.source != GloveSource::Serial {
+            continue;
+        }
+        let transport = SerialTransport {
+            port: device.port.clone().unwrap_or_else(|| config.serial.port.clone()),
+            config: config.serial.clone(),
+        };
+        spawn_transport(&runtime, &queue, device, &schema, transport);
+    }
+}
+
+/// Reads one glove from a serial port, reopening it whenever it goes away.
+pub struct SerialTransport {
+    port: String,
+    config: SerialConfig,
+}
+
+impl GloveTransport for SerialTransport {
+    fn name(&self) -> String {
+        format!("serial {}", self.port)
+    }
+
+    async fn run(self, mut sink: GloveSink) {
+        loop {
+            sink.set_connection(GloveConnection::Connecting).await;
+            let opened = serialport::new(self.port.as_str(), self.config.baud_rate)
+                .timeout(READ_TIMEOUT)
+                .open();
+            match opened {
+                Ok(port) => {
+                    info!("Opened serial port {} at {} baud", self.port, self.config.baud_rate);
+                    sink.set_connection(GloveConnection::Streaming).await;
+                    stream_port(&mut sink, self.config.protocol, port).await;
+                }
+                Err(err) => {
+                    warn!("Error opening serial port {}: {}", self.port, err);
+                }
+            }
+
+            sink.set_connection(GloveConnection::Disconnected).await;
+            time::sleep(RETRY_INTERVAL).await;
+        }
+    }
+}
+
+async fn stream_port(sink: &mut GloveSink, protocol: SerialProtocol, port: Box<dyn SerialPort>) {
+    // Serial reads block, so they get their own thread and hand bytes over a channel
+    let (bytes_tx, mut bytes_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+    let read_task = task::spawn_blocking(move || read_port(port, bytes_tx));
+
+    let mut decoder = FrameDecoder::new(protocol, sink.schema().clone());
+    while let Some(bytes) = bytes_rx.recv().await {
+        decoder.push(&bytes);
+        while let Ok(bytes) = bytes_rx.try_recv() {
+            decoder.push(&bytes);
+        }
+
+        let mut samples = Vec::new();
+        while let Some(frame) = decoder.next_frame() {
+            match frame {
+                Ok(data) => samples.push(sink.sample(data)),
+                Err(err) => {
+                    // Counted in the link diagnostics, so only worth a line when debugging
+                    debug!("Serial framing error, resyncing: {}", err);
+                    sink.decode_failed();
+                }
+            }
+        }
+
+        sink.send(samples).await;
+    }
+
+    let _ = read_task.await;
+}
+
+fn read_port(mut port: Box<dyn SerialPort>, bytes_tx: mpsc::UnboundedSender<Vec<u8>>) {
+    let mut buf = [0u8; 256];
+    while !bytes_tx.is_closed() {
+        match port.read(&mut buf) {
+            Ok(0) => continue,
+            Ok(len) => {
+                if bytes_tx.send(buf[..len].to_vec()).is_err() {
+                    break;
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => continue,
+            Err(err) => {
+                warn!("Error reading serial port: {}", err);
+                break;
+            }
+        }
+    }
+}
+
+/// Why some bytes were thrown away instead of becoming a sample.
+#[derive(Debug)]
+pub enum FrameError {
+    Checksum { expected: u8, actual: u8 },
+    BadLength { kind: u8, len: usize },
+    Decode(DecodeError),
+    BadLine(String),
+    LineTooLong,
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Checksum { expected, actual } => write!(f, "checksum {:02x}, expected {:02x}", actual, expected),
+            FrameError::BadLength { kind, len } => write!(f, "frame type {:02x} with {} byte payload", kind, len),
+            FrameError::Decode(err) => err.fmt(f),
+            FrameError::BadLine(line) => write!(f, "malformed line {:?}", line),
+            FrameError::LineTooLong => write!(f, "no newline within {} bytes", MAX_LINE),
+        }
+    }
+}
+
+/// Turns a raw byte stream into samples, skipping ahead to the next frame whenever
+/// something doesn't add up.
+pub struct FrameDecoder {
+    protocol: SerialProtocol,
+    schema: GloveSchema,
+    buf: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new(protocol: SerialProtocol, schema: GloveSchema) -> Self {
+        Self {
+            protocol,
+            schema,
+            buf: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Returns the next complete frame, or None if more bytes are needed.
+    pub fn next_frame(&mut self) -> Option<Result<SampleData, FrameError>> {
+        match self.protocol {
+            SerialProtocol::Binary => self.next_binary(),
+            SerialProtocol::Line => self.next_line(),
+        }
+    }
+
+    fn next_binary(&mut self) -> Option<Result<SampleData, FrameError>> {
+        let Some(start) = self.buf.windows(2).position(|window| window == SYNC) else {
+            // Nothing that looks like a frame yet. Hang on to a trailing half sync word.
+            let keep = if self.buf.last() == Some(&SYNC[0]) { 1 } else { 0 };
+            self.buf.drain(..self.buf.len() - keep);
+            return None;
+        };
+        self.buf.drain(..start);
+
+        if self.buf.len() < 4 {
+            return None;
+        }
+        let kind = self.buf[2];
+        let len = self.buf[3] as usize;
+        if len > MAX_PAYLOAD {
+            self.buf.drain(..1);
+            return Some(Err(FrameError::BadLength { kind, len }));
+        }
+
+        let frame_len = 4 + len + 1;
+        if self.buf.len() < frame_len {
+            return None;
+        }
+
+        let expected = self.buf[frame_len - 1];
+        let actual = checksum(&self.buf[2..frame_len - 1]);
+        if expected != actual {
+            // The sync word may have been part of a payload. Skip it and look again.
+            self.buf.drain(..1);
+            return Some(Err(FrameError::Checksum { expected, actual }));
+        }
+
+        let frame = self.schema.decode(kind, &self.buf[4..frame_len - 1]).map_err(FrameError::Decode);
+        self.buf.drain(..frame_len);
+        Some(frame)
+    }
+
+    fn next_line(&mut self) -> Option<Result<SampleData, FrameError>> {
+        loop {
+            let Some(end) = self.buf.iter().position(|byte| *byte == b'\n') else {
+                if self.buf.len() > MAX_LINE {
+                    self.buf.clear();
+                    return Some(Err(FrameError::LineTooLong));
+                }
+                return None;
+            };
+
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim();
+
+            // Anything that isn't a data line is the firmware printing, not an error
+            let is_data = line
+                .split_once(',')
+                .is_some_and(|(tag, _)| self.schema.by_tag(tag).is_some());
+            if !is_data {
+                continue;
+            }
+            return Some(parse_line(&self.schema, line));
+        }
+    }
+}
+
+fn parse_line(schema: &GloveSchema, line: &str) -> Result<SampleData, FrameError> {
+    let bad_line = || FrameError::BadLine(line.to_string());
+
+    let (body, sum) = line.split_once('*').ok_or_else(bad_line)?;
+    let expected = u8::from_str_radix(sum.trim(), 16).map_err(|_| bad_line())?;
+    let actual = checksum(body.as_bytes());
+    if expected != actual {
+        return Err(FrameError::Checksum { expected, actual });
+    }
+
+    let (tag, fields) = body.split_once(',').ok_or_else(bad_line)?;
+    let packet = schema.by_tag(tag).ok_or_else(bad_line)?;
+    let fields: Vec<&str> = fields.split(',').map(str::trim).collect();
+    if fields.len() != packet.channel_count() {
+        return Err(bad_line());
+    }
+    let values: Vec<f32> = fields
+        .iter()
+        .map(|value| value.parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| bad_line())?;
+    // Parsed again on its own, as an f32 would round it
+    let timestamp = packet
+        .channels()
+        .position(|channel| channel.role == Some(ChannelRole::Timestamp))
+        .map(|i| fields[i].parse::<f64>())
+        .transpose()
+        .map_err(|_| bad_line())?;
+
+    Ok(SampleData {
+        packet: packet.id,
+        values,
+        timestamp,
+        raw: line.as_bytes().to_vec(),
+    })
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |sum, byte| sum ^ byte)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{KIND_FLEX, KIND_IMU};
+
+    const FLEX_VALUES: [f32; 5] = [1000.0, 2000.0, 3000.0, 4000.0, 5000.0];
+
+    /// The payload of a built-in schema packet.
+    fn payload(kind: u8, values: &[f32]) -> Vec<u8> {
+        GloveSchema::default().by_id(kind).unwrap().encode(values)
+    }
+
+    fn frame(kind: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = SYNC.to_vec();
+        frame.push(kind);
+        frame.push(payload.len() as u8);
+        frame.extend_from_slice(payload);
+        frame.push(checksum(&frame[2..]));
+        frame
+    }
+
+    fn flex_frame() -> Vec<u8> {
+        frame(KIND_FLEX, &payload(KIND_FLEX, &FLEX_VALUES))
+    }
+
+    fn decode_all(bytes: &[u8]) -> Vec<Result<SampleData, FrameError>> {
+        let mut decoder = FrameDecoder::new(SerialProtocol::Binary, GloveSchema::default());
+        decoder.push(bytes);
+        std::iter::from_fn(|| decoder.next_frame()).collect()
+    }
+
+    /// Equal to within what goes missing in fixed point.
+    fn close(actual: &[f32], expected: &[f32]) -> bool {
+        actual.len() == expected.len() && actual.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-4)
+    }
+
+    fn values(frame: &Result<SampleData, FrameError>) -> &[f32] {
+        match frame {
+            Ok(data) => &data.values,
+            Err(err) => panic!("expected a sample, got {}", err),
+        }
+    }
+
+    #[test]
+    fn decodes_good_frames() {
+        let imu = [0.0, 0.0, 9.81, -45.0, 0.0, 1.5];
+        let mut bytes = flex_frame();
+        bytes.extend(frame(KIND_IMU, &payload(KIND_IMU, &imu)));
+
+        let frames = decode_all(&bytes);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(values(&frames[0]), FLEX_VALUES);
+        assert_eq!(frames[1].as_ref().unwrap().packet, KIND_IMU);
+        assert!(close(values(&frames[1]), &imu));
+    }
+
+    #[test]
+    fn decodes_frames_split_across_reads() {
+        let mut decoder = FrameDecoder::new(SerialProtocol::Binary, GloveSchema::default());
+        let bytes = flex_frame();
+        for byte in &bytes[..bytes.len() - 1] {
+            decoder.push(std::slice::from_ref(byte));
+            assert!(decoder.next_frame().is_none());
+        }
+        decoder.push(&bytes[bytes.len() - 1..]);
+        assert_eq!(values(&decoder.next_frame().unwrap()), FLEX_VALUES);
+        assert!(decoder.next_frame().is_none());
+    }
+
+    #[test]
+    fn rejects_bad_checksum() {
+        let mut bytes = flex_frame();
+        *bytes.last_mut().unwrap() ^= 0xFF;
+        bytes.extend(flex_frame());
+
+        let frames = decode_all(&bytes);
+        assert!(matches!(frames[0], Err(FrameError::Checksum { .. })));
+        assert_eq!(values(frames.last().unwrap()), FLEX_VALUES);
+        assert_eq!(frames.iter().filter(|frame| frame.is_ok()).count(), 1);
+    }
+
+    #[test]
+    fn keeps_sync_word_inside_payload() {
+        // 0xA55A is a finger reading as well as the sync word
+        let flex = [0xA55A, 0x5AA5, 0xA5A5, 0x5A5A, 0xA55A].map(|finger: u16| finger as f32);
+        let frames = decode_all(&frame(KIND_FLEX, &payload(KIND_FLEX, &flex)));
+        assert_eq!(frames.len(), 1);
+        assert_eq!(values(&frames[0]), flex);
+    }
+
+    #[test]
+    fn skips_false_sync_inside_payload() {
+        // Opened mid-frame, so the first sync word seen is part of a payload
+        let flex = [1000, 0xA55A, 0x0102, 0x0304, 3000].map(|finger: u16| finger as f32);
+        let cut = frame(KIND_FLEX, &payload(KIND_FLEX, &flex));
+        let mut bytes = cut[6..].to_vec();
+        bytes.extend(flex_frame());
+
+        let frames = decode_all(&bytes);
+        assert!(matches!(frames[0], Err(FrameError::Checksum { .. })));
+        assert_eq!(values(frames.last().unwrap()), FLEX_VALUES);
+        assert_eq!(frames.iter().filter(|frame| frame.is_ok()).count(), 1);
+    }
+
+    #[test]
+    fn resyncs_after_garbage() {
+        let mut bytes = b"boot v1.2\r\n\xA5\x00\xFF\x5A\xA5".to_vec();
+        bytes.extend(flex_frame());
+        bytes.extend([0x00, 0xA5]);
+        bytes.extend(flex_frame());
+
+        let frames = decode_all(&bytes);
+        assert_eq!(frames.len(), 2);
+        assert!(frames.iter().all(|frame| values(frame) == FLEX_VALUES));
+    }
+
+    #[test]
+    fn rejects_length_over_max_payload() {
+        let mut bytes = vec![SYNC[0], SYNC[1], KIND_FLEX, MAX_PAYLOAD as u8 + 1];
+        bytes.extend(flex_frame());
+
+        let frames = decode_all(&bytes);
+        assert!(matches!(frames[0], Err(FrameError::BadLength { kind: KIND_FLEX, len }) if len == MAX_PAYLOAD + 1));
+        assert_eq!(frames.len(), 2);
+        assert_eq!(values(&frames[1]), FLEX_VALUES);
+    }
+
+    #[test]
+    fn rejects_payload_of_wrong_length_for_packet() {
+        let frames = decode_all(&frame(KIND_FLEX, &[0; 4]));
+        assert!(matches!(frames[..], [Err(FrameError::Decode(DecodeError::WrongLength { .. }))]));
+    }
+
+    /// A line protocol line with its checksum, e.g. `F,1,2,3,4,5*0F\n`.
+    fn line(body: &str) -> String {
+        format!("{}*{:02X}\n", body, checksum(body.as_bytes()))
+    }
+
+    fn decode_lines(bytes: &[u8]) -> Vec<Result<SampleData, FrameError>> {
+        let mut decoder = FrameDecoder::new(SerialProtocol::Line, GloveSchema::default());
+        decoder.push(bytes);
+        std::iter::from_fn(|| decoder.next_frame()).collect()
+    }
+
+    #[test]
+    fn parses_lines() {
+        let schema = GloveSchema::default();
+        let body = "F,1000,2000,3000,4000,5000";
+        let data = parse_line(&schema, line(body).trim()).unwrap();
+        assert_eq!(data.packet, KIND_FLEX);
+        assert_eq!(data.values, FLEX_VALUES);
+        assert_eq!(data.timestamp, None);
+        assert_eq!(data.raw, line(body).trim().as_bytes());
+
+        let data = parse_line(&schema, line("I, 0.5,-0.25,9.81, 10,20,-30").trim()).unwrap();
+        assert_eq!(data.packet, KIND_IMU);
+        assert_eq!(data.values, [0.5, -0.25, 9.81, 10.0, 20.0, -30.0]);
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        let schema = GloveSchema::default();
+        let bad_line = |line: &str| matches!(parse_line(&schema, line), Err(FrameError::BadLine(_)));
+
+        assert!(bad_line("F,1000,2000,3000,4000,5000"), "no checksum");
+        assert!(bad_line("F,1000,2000,3000,4000,5000*"), "no checksum digits");
+        assert!(bad_line("F,1000,2000,3000,4000,5000*ZZ"), "checksum not hex");
+        assert!(bad_line(line("F,1000,2000,3000,4000").trim()), "too few values");
+        assert!(bad_line(line("F,1000,2000,3000,4000,5000,6000").trim()), "too many values");
+        assert!(bad_line(line("F,1000,2000,three,4000,5000").trim()), "not a number");
+        assert!(bad_line(line("X,1000,2000,3000,4000,5000").trim()), "unknown tag");
+
+        let mut corrupted = line("F,1000,2000,3000,4000,5000").trim().to_string();
+        corrupted.replace_range(2..3, "9");
+        assert!(matches!(parse_line(&schema, &corrupted), Err(FrameError::Checksum { .. })));
+    }
+
+    #[test]
+    fn decodes_lines_split_across_reads() {
+        let mut decoder = FrameDecoder::new(SerialProtocol::Line, GloveSchema::default());
+        let bytes = line("F,1000,2000,3000,4000,5000");
+        let (first, rest) = bytes.as_bytes().split_at(9);
+        decoder.push(first);
+        assert!(decoder.next_frame().is_none());
+        decoder.push(rest);
+        assert_eq!(values(&decoder.next_frame().unwrap()), FLEX_VALUES);
+        assert!(decoder.next_frame().is_none());
+    }
+
+    #[test]
+    fn skips_chatter_and_resyncs_after_garbage() {
+        // Opened mid-line, then the firmware printing, then a line mangled on the wire
+        let mut bytes = b"00,4000,5000*1C\r\nboot v1.2\r\n\x00\xFF\n".to_vec();
+        let mut mangled = line("F,1000,2000,3000,4000,5000");
+        mangled.replace_range(4..5, "7");
+        bytes.extend(mangled.as_bytes());
+        bytes.extend(line("F,1000,2000,3000,4000,5000").replace('\n', "\r\n").as_bytes());
+
+        let frames = decode_lines(&bytes);
+        assert_eq!(frames.len(), 2);
+        assert!(matches!(frames[0], Err(FrameError::Checksum { .. })));
+        assert_eq!(values(&frames[1]), FLEX_VALUES);
+    }
+
+    #[test]
+    fn drops_overlong_lines() {
+        let mut bytes = vec![b'F'; MAX_LINE + 1];
+        let mut decoder = FrameDecoder::new(SerialProtocol::Line, GloveSchema::default());
+        decoder.push(&bytes);
+        assert!(matches!(decoder.next_frame(), Some(Err(FrameError::LineTooLong))));
+
+        // The tail of the long line goes with the next newline, and the stream carries on
+        bytes = b"FFFF\n".to_vec();
+        bytes.extend(line("F,1000,2000,3000,4000,5000").as_bytes());
+        decoder.push(&bytes);
+        assert_eq!(values(&decoder.next_frame().unwrap()), FLEX_VALUES);
+        assert!(decoder.next_frame().is_none());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn streams_from_pty() {
+        use std::io::Write;
+
+        use serialport::TTYPort;
+
+        use crate::config::GloveDevice;
+        use crate::ruka::Handedness;
+        use crate::testing::{channels, connection, glove_app, start_transport, update_until};
+
+        // The transport opens the slave end by name, like a real port. Ours stays open so
+        // the master keeps working.
+        let (mut master, slave) = TTYPort::pair().expect("no pty pair");
+        let port = slave.name().expect("pty without a name");
+
+        let device = GloveDevice {
+            source: GloveSource::Serial,
+            port: Some(port.clone()),
+            ..GloveDevice::new(Handedness::Left)
+        };
+        let config = GloveConfig {
+            gloves: vec![device.clone()],
+            ..Default::default()
+        };
+        let transport = SerialTransport {
+            port,
+            config: config.serial.clone(),
+        };
+        let mut app = glove_app(config, GloveSchema::default());
+        start_transport(&mut app, &device, transport);
+
+        update_until(&mut app, Duration::from_secs(2), "the port to open", |world| {
+            connection(world, Handedness::Left) == GloveConnection::Streaming
+        });
+
+        let mut bytes = b"\x00garbage".to_vec();
+        bytes.extend(flex_frame());
+        bytes.extend(frame(KIND_IMU, &payload(KIND_IMU, &[0.5, -0.25, 9.81, 10.0, 20.0, -30.0])));
+
+        // Keep sending until the reader thread is up and has seen a whole frame
+        let expected = [1000.0, 2000.0, 3000.0, 4000.0, 5000.0, 0.5, -0.25, 9.81, 10.0, 20.0, -30.0];
+        let mut frames = 0;
+        update_until(&mut app, Duration::from_secs(2), "samples from the pty", |world| {
+            if frames % 20 == 0 {
+                master.write_all(&bytes).expect("writing to the pty");
+            }
+            frames += 1;
+            close(&channels(world, Handedness::Left), &expected)
+        });
+        drop(slave);
+    }
+}
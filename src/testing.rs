@@ -0,0 +1,81 @@
+// Headless app for driving the glove transports in tests: just the ECS, the background runtime
+// and the glove entities, with no window and no hardware.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bevy::app::{App, Startup, Update};
+use bevy::ecs::event::{Event, EventReader};
+use bevy::ecs::system::{ResMut, Resource};
+use bevy::ecs::world::World;
+use bevy::MinimalPlugins;
+
+use crate::asyncs::TokioTasksRuntime;
+use crate::config::{GloveConfig, GloveDevice};
+use crate::ruka::{glove_mut, spawn_gloves, ActiveHands, GloveConnection, Handedness, RukaInput};
+use crate::schema::GloveSchema;
+use crate::transport::{spawn_transport, GloveTransport, GloveTransportPlugin, SampleQueue};
+
+/// An app with the gloves in `config` spawned but no sources started.
+pub fn glove_app(config: GloveConfig, schema: GloveSchema) -> App {
+    let mut app = App::new();
+    app
+        .add_plugins(MinimalPlugins)
+        .insert_resource(config)
+        .insert_resource(schema)
+        .add_plugins(GloveTransportPlugin)
+        .init_resource::<ActiveHands>()
+        .add_systems(Startup, spawn_gloves)
+    ;
+    app
+}
+
+/// Starts a transport for `device` the way the source plugins do.
+pub fn start_transport<T: GloveTransport>(app: &mut App, device: &GloveDevice, transport: T) {
+    let world = &app.world;
+    spawn_transport(
+        world.resource::<TokioTasksRuntime>(),
+        world.resource::<SampleQueue>(),
+        device,
+        world.resource::<GloveSchema>(),
+        transport,
+    );
+}
+
+/// Runs frames until `done` holds, failing the test after `timeout`.
+pub fn update_until(app: &mut App, timeout: Duration, what: &str, mut done: impl FnMut(&mut World) -> bool) {
+    let deadline = Instant::now() + timeout;
+    loop {
+        app.update();
+        if done(&mut app.world) {
+            return;
+        }
+        assert!(Instant::now() < deadline, "timed out after {:?} waiting for {}", timeout, what);
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+pub fn connection(world: &mut World, hand: Handedness) -> GloveConnection {
+    *glove_mut::<GloveConnection>(world, hand).expect("no glove on that hand")
+}
+
+/// Latest value of every schema channel of a glove.
+pub fn channels(world: &mut World, hand: Handedness) -> Vec<f32> {
+    let count = world.resource::<GloveSchema>().channel_count();
+    let ruka = glove_mut::<RukaInput>(world, hand).expect("no glove on that hand");
+    ruka.get_all_for_debug()[..count].to_vec()
+}
+
+/// What `collect` picked out of every event of one type so far, in order.
+#[derive(Resource)]
+pub struct Collected<T>(pub Vec<T>);
+
+/// Keeps `pick(event)` for every event of type `E` sent from now on.
+pub fn collect<E: Event, T: Send + Sync + 'static>(app: &mut App, pick: fn(&E) -> T) {
+    app
+        .insert_resource(Collected::<T>(Vec::new()))
+        .add_systems(Update, move |mut events: EventReader<E>, mut collected: ResMut<Collected<T>>| {
+            collected.0.extend(events.read().map(pick));
+        })
+    ;
+}
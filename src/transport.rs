@@ -0,0 +1,337 @@
+// Glove data sources and the path their samples take into the app.
+// BLE is one source; anything else that can produce flex and IMU readings implements
+// GloveTransport and ends up in the same RukaInput the rest of the app reads from.
+//
+// Samples don't wait for the main thread: transports push them into a bounded SampleQueue
+// and drain_samples hands every one of them to the ECS at the start of the next frame, both
+// into RukaInput and as GloveSample events for anything that wants the full stream.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bevy::app::{App, Plugin, PreUpdate, Update};
+use bevy::ecs::component::Component;
+use bevy::ecs::event::{Event, EventWriter};
+use bevy::ecs::system::{Query, Res, Resource};
+use bevy::log::{error, info};
+use bevy::math::Vec3;
+use crossbeam_queue::ArrayQueue;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use crate::asyncs::{TaskContext, TokioTasksPlugin, TokioTasksRuntime};
+use crate::config::{GloveConfig, GloveDevice};
+use crate::command::{Command, CommandQueue, GloveCommandFailed};
+use crate::device::DeviceInfo;
+use crate::diagnostics::{LinkDiagnostics, LinkReport};
+use crate::error::{log_glove_errors, GloveError, GloveErrorEvent};
+use crate::net::UdpForwarder;
+use crate::protocol::SampleData;
+use crate::ruka::{glove_entity, glove_mut, GloveConnection, Handedness, RukaInput};
+use crate::schema::{ChannelRole, GloveSchema};
+
+pub struct GloveTransportPlugin;
+
+impl Plugin for GloveTransportPlugin {
+    fn build(&self, app: &mut App) {
+        let capacity = app.world.resource::<GloveConfig>().sample_queue;
+        app
+            .add_plugins(TokioTasksPlugin::default())
+            .insert_resource(SampleQueue::new(capacity))
+            .add_event::<GloveSample>()
+            .add_event::<GloveErrorEvent>()
+            .add_systems(PreUpdate, drain_samples)
+            .add_systems(Update, log_glove_errors)
+        ;
+    }
+}
+
+/// A source of glove samples, run as a background task for one hand.
+pub trait GloveTransport: Send + 'static {
+    /// Short name of the source for logging, e.g. "BLE".
+    fn name(&self) -> String;
+
+    /// Runs the source for as long as it has data, pushing every sample into the sink.
+    /// Sources that can lose their device are expected to keep retrying on their own.
+    fn run(self, sink: GloveSink) -> impl Future<Output = ()> + Send;
+
+    /// Whether `run` cleans up and returns by itself once `GloveSink::shutdown` is cancelled.
+    /// Transports that don't are simply dropped when the app exits.
+    fn handles_shutdown(&self) -> bool {
+        false
+    }
+}
+
+/// One reading from a glove, stamped with when the host received it. Every sample that makes
+/// it through the queue is also sent as an event, in the order it arrived.
+#[derive(Event, Debug, Clone)]
+pub struct GloveSample {
+    pub hand: Handedness,
+    /// Counts up by one per sample of this glove, so a jump means samples were dropped
+    pub seq: u64,
+    pub t_host: Instant,
+    /// The glove's own clock, for packets with a timestamp channel
+    pub t_device: Option<Duration>,
+    /// Flex sensor values in this packet, thumb first. Empty if it has none.
+    pub flex: Vec<f32>,
+    /// Accelerometer and gyroscope values in this packet, if it has any
+    pub imu: Option<ImuReading>,
+    /// The packet as decoded, every channel in schema order
+    pub data: SampleData,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImuReading {
+    pub accel: Option<Vec3>,
+    pub gyro: Option<Vec3>,
+}
+
+/// Samples on their way from the transport tasks to the next frame.
+///
+/// Bounded so a stalled app can't eat all memory. When it's full, pushing drops the oldest
+/// sample, since newer data is worth more to everything downstream. Drops show up as gaps in
+/// `GloveSample::seq` and in the dropped_samples link diagnostic.
+#[derive(Resource, Clone)]
+pub struct SampleQueue(Arc<ArrayQueue<GloveSample>>);
+
+impl SampleQueue {
+    fn new(capacity: usize) -> Self {
+        Self(Arc::new(ArrayQueue::new(capacity.max(1))))
+    }
+
+    fn push(&self, sample: GloveSample) {
+        let _ = self.0.force_push(sample);
+    }
+}
+
+/// Starts a transport on the background runtime, feeding the glove entity of `device`.
+pub fn spawn_transport<T: GloveTransport>(
+    runtime: &TokioTasksRuntime,
+    queue: &SampleQueue,
+    device: &GloveDevice,
+    schema: &GloveSchema,
+    transport: T,
+) {
+    let hand = device.hand;
+    info!("Starting {} source for {:?} glove", transport.name(), hand);
+
+    let forwarder = device.forward_to.as_ref().and_then(|target| {
+        match UdpForwarder::new(target, hand, schema.clone()) {
+            Ok(forwarder) => {
+                info!("Relaying {:?} glove to {}", hand, target);
+                Some(forwarder)
+            }
+            Err(err) => {
+                error!("Error setting up relay to {}: {}", target, err);
+                None
+            }
+        }
+    });
+
+    let schema = schema.clone();
+    let queue = queue.clone();
+    runtime.spawn_background_task(move |ctx| async move {
+        let shutdown = ctx.shutdown_token();
+        let sink = GloveSink::new(ctx, hand, schema, queue, forwarder);
+        if transport.handles_shutdown() {
+            transport.run(sink).await;
+            return;
+        }
+        tokio::select! {
+            _ = transport.run(sink) => {}
+            _ = shutdown.cancelled() => {}
+        }
+    });
+}
+
+/// Where a transport sends its samples and connection state. Owned by the transport's task.
+pub struct GloveSink {
+    ctx: TaskContext,
+    hand: Handedness,
+    schema: GloveSchema,
+    queue: SampleQueue,
+    forwarder: Option<UdpForwarder>,
+    /// `seq` of the next sample
+    next_seq: u64,
+    shutdown: CancellationToken,
+    /// Link statistics gathered since the last `send`
+    link: LinkReport,
+}
+
+impl GloveSink {
+    fn new(ctx: TaskContext, hand: Handedness, schema: GloveSchema, queue: SampleQueue, forwarder: Option<UdpForwarder>) -> Self {
+        Self {
+            shutdown: ctx.shutdown_token(),
+            ctx,
+            hand,
+            schema,
+            queue,
+            forwarder,
+            next_seq: 0,
+            link: LinkReport::default(),
+        }
+    }
+
+    /// Cancelled when the app exits, see `GloveTransport::handles_shutdown`.
+    pub fn shutdown(&self) -> &CancellationToken {
+        &self.shutdown
+    }
+
+    /// What the glove sends, for decoding its payloads.
+    pub fn schema(&self) -> &GloveSchema {
+        &self.schema
+    }
+
+    /// Stamps freshly received data with the current host time and the next sequence number,
+    /// and picks out what its channels are for.
+    pub fn sample(&mut self, data: SampleData) -> GloveSample {
+        let t_host = Instant::now();
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        let (flex, imu) = match self.schema.by_id(data.packet) {
+            Some(packet) => {
+                let vec3 = |role| match packet.role_values(&data.values, role)[..] {
+                    [x, y, z] => Some(Vec3::new(x, y, z)),
+                    _ => None,
+                };
+                let (accel, gyro) = (vec3(ChannelRole::Accel), vec3(ChannelRole::Gyro));
+                let imu = (accel.is_some() || gyro.is_some()).then_some(ImuReading { accel, gyro });
+                (packet.role_values(&data.values, ChannelRole::Flex), imu)
+            }
+            None => (Vec::new(), None),
+        };
+        let t_device = data.timestamp.map(|ms| Duration::from_secs_f64(ms.max(0.0) / 1000.0));
+
+        GloveSample {
+            hand: self.hand,
+            seq,
+            t_host,
+            t_device,
+            flex,
+            imu,
+            data,
+        }
+    }
+
+    /// Latest signal strength of the link, in dBm.
+    pub fn set_rssi(&mut self, rssi: i16) {
+        self.link.rssi = Some(rssi);
+    }
+
+    /// Counts a payload that arrived but could not be decoded.
+    pub fn decode_failed(&mut self) {
+        self.link.decode_failures += 1;
+    }
+
+    /// Counts a jump in the sequence numbers of a source that has them.
+    pub fn sequence_gap(&mut self) {
+        self.link.sequence_gaps += 1;
+    }
+
+    /// Queues a batch of samples for the next frame, in order. Doesn't wait for the main
+    /// thread, except to hand over link statistics when there are any.
+    pub async fn send(&mut self, samples: Vec<GloveSample>) {
+        for sample in samples {
+            if let Some(forwarder) = self.forwarder.as_mut() {
+                forwarder.forward(&sample);
+            }
+            self.queue.push(sample);
+        }
+
+        if self.link.is_empty() {
+            return;
+        }
+        let hand = self.hand;
+        let link = std::mem::take(&mut self.link);
+        self.ctx.run_on_main_thread(move |main_ctx| {
+            if let Some(mut diagnostics) = glove_mut::<LinkDiagnostics>(main_ctx.world, hand) {
+                diagnostics.apply(link);
+            }
+        }).await;
+    }
+
+    /// Lets the app send commands to this glove. Call once, for transports that can write to it.
+    pub async fn accept_commands(&mut self) -> mpsc::UnboundedReceiver<Command> {
+        let (command_tx, command_rx) = mpsc::unbounded_channel();
+        self.insert_component(CommandQueue(command_tx)).await;
+        command_rx
+    }
+
+    /// Adds a component to the glove entity, e.g. a channel for the app to reach the transport.
+    pub async fn insert_component<C: Component>(&mut self, component: C) {
+        let hand = self.hand;
+        self.ctx.run_on_main_thread(move |main_ctx| {
+            if let Some(entity) = glove_entity(main_ctx.world, hand) {
+                main_ctx.world.entity_mut(entity).insert(component);
+            }
+        }).await;
+    }
+
+    /// Reports a command that could not be written back to the app.
+    pub async fn command_failed(&mut self, command: Command, error: String) {
+        let hand = self.hand;
+        self.ctx.run_on_main_thread(move |main_ctx| {
+            main_ctx.world.send_event(GloveCommandFailed { hand, command, error });
+        }).await;
+    }
+
+    /// Reports an error the transport ran into, e.g. a failed connection attempt.
+    pub async fn report_error(&mut self, error: GloveError) {
+        let hand = self.hand;
+        self.ctx.run_on_main_thread(move |main_ctx| {
+            main_ctx.world.send_event(GloveErrorEvent { hand, error });
+        }).await;
+    }
+
+    /// Updates what is known about the glove hardware, e.g. after reading its battery level.
+    pub async fn update_device_info(&mut self, update: impl FnOnce(&mut DeviceInfo) + Send + 'static) {
+        let hand = self.hand;
+        self.ctx.run_on_main_thread(move |main_ctx| {
+            if let Some(mut info) = glove_mut::<DeviceInfo>(main_ctx.world, hand) {
+                update(&mut info);
+            }
+        }).await;
+    }
+
+    /// Moves the glove along its connection lifecycle. The glove counts as initialised
+    /// for as long as it is streaming.
+    pub async fn set_connection(&mut self, state: GloveConnection) {
+        let hand = self.hand;
+        self.ctx.run_on_main_thread(move |main_ctx| {
+            if let Some(mut connection) = glove_mut::<GloveConnection>(main_ctx.world, hand) {
+                if *connection != state {
+                    info!("{:?} glove connection: {:?} -> {:?}", hand, *connection, state);
+                    *connection = state;
+                }
+            }
+            if let Some(mut ruka) = glove_mut::<RukaInput>(main_ctx.world, hand) {
+                ruka.set_init(state == GloveConnection::Streaming);
+            }
+        }).await;
+    }
+}
+
+/// Applies everything that was queued since the last frame to the glove it belongs to and
+/// passes it on as events. This is the single point where data from any source enters the ECS.
+fn drain_samples(
+    queue: Res<SampleQueue>,
+    schema: Res<GloveSchema>,
+    mut gloves: Query<(&Handedness, &mut RukaInput, &mut LinkDiagnostics)>,
+    mut events: EventWriter<GloveSample>,
+) {
+    // Only what's there now, so transports that keep pushing can't hold up the frame
+    for _ in 0..queue.0.len() {
+        let Some(sample) = queue.0.pop() else {
+            break;
+        };
+        if let Some((_, mut ruka, mut link)) = gloves.iter_mut().find(|(hand, _, _)| **hand == sample.hand) {
+            link.arrived(&sample);
+            if let Some(offset) = schema.channel_offset(sample.data.packet) {
+                ruka.update_channels(offset, &sample.data.values, sample.t_host, sample.t_device);
+            }
+        }
+        events.send(sample);
+    }
+}